@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::proposal::FROM_NANO;
+use crate::{events, Contract};
+
+/// Budget a member can spend without a vote.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Allowance {
+    /// amount left to spend
+    pub amount: Balance,
+    /// Unix timestamp (in seconds) after which the allowance can't be spent.
+    pub expiry: u64,
+}
+
+/// Record of a transfer made from an allowance.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Spend {
+    pub member: AccountId,
+    pub dest: AccountId,
+    pub amount: Balance,
+    /// Unix timestamp (in seconds) of the spend.
+    pub timestamp: u64,
+}
+
+/// JSON compatible return type for Allowance.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AllowanceOut {
+    pub amount: U128,
+    pub expiry: U64,
+}
+
+impl From<Allowance> for AllowanceOut {
+    fn from(a: Allowance) -> AllowanceOut {
+        AllowanceOut {
+            amount: a.amount.into(),
+            expiry: a.expiry.into(),
+        }
+    }
+}
+
+/// JSON compatible return type for Spend.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendOut {
+    pub member: AccountId,
+    pub dest: AccountId,
+    pub amount: U128,
+    pub timestamp: U64,
+}
+
+impl From<Spend> for SpendOut {
+    fn from(s: Spend) -> SpendOut {
+        SpendOut {
+            member: s.member,
+            dest: s.dest,
+            amount: s.amount.into(),
+            timestamp: s.timestamp.into(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Transfers `amount` from the caller allowance to `dest`, without a vote.
+    The caller has to attach a deposit to cover the storage cost of the spend record,
+    the change is refunded. An exhausted allowance is removed. */
+    #[payable]
+    pub fn spend_allowance(&mut self, dest: ValidAccountId, amount: U128) -> Promise {
+        let member = env::predecessor_account_id();
+        let mut a = self
+            .allowances
+            .get(&member)
+            .expect(&format!("you ({}) don't have an allowance", member));
+        let t = env::block_timestamp() / FROM_NANO;
+        assert!(t <= a.expiry, "allowance expired");
+        let amount: Balance = amount.into();
+        assert!(
+            amount <= a.amount,
+            "not enough allowance (remaining: {}, required: {})",
+            a.amount,
+            amount
+        );
+        let storage_start = env::storage_usage();
+        a.amount -= amount;
+        if a.amount == 0 {
+            self.allowances.remove(&member);
+        } else {
+            self.allowances.insert(&member, &a);
+        }
+        self.reserved -= amount;
+        self.spends.push(&Spend {
+            member: member.clone(),
+            dest: dest.clone().into(),
+            amount,
+            timestamp: t,
+        });
+        self.refund_storage(storage_start, false);
+        events::treasury("allowance_spent", None, &member, amount);
+        Promise::new(dest.into()).transfer(amount)
+    }
+
+    /// Removes an expired allowance of the `member` and releases its remaining funds.
+    /// Anyone can call this function.
+    pub fn release_allowance(&mut self, member: ValidAccountId) {
+        let member: AccountId = member.into();
+        let a = self
+            .allowances
+            .get(&member)
+            .expect(&format!("{} doesn't have an allowance", member));
+        assert!(
+            env::block_timestamp() / FROM_NANO > a.expiry,
+            "allowance is not expired"
+        );
+        self.remove_allowance(&member);
+    }
+
+    /// Returns member allowance, if it exists.
+    pub fn allowance(&self, member: ValidAccountId) -> Option<AllowanceOut> {
+        let member: AccountId = member.into();
+        self.allowances.get(&member).map(AllowanceOut::from)
+    }
+
+    /// Returns up to `limit` allowance spends, starting from the `from_index` spend.
+    pub fn spends(&self, from_index: u64, limit: u64) -> Vec<SpendOut> {
+        let end = self.spends.len().min(from_index.saturating_add(limit));
+        (from_index..end)
+            .map(|i| self.spends.get(i).unwrap().into())
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Sets member allowance and reserves its funds. The previous allowance of the member
+    /// is replaced.
+    pub(crate) fn grant_allowance(&mut self, member: &AccountId, a: Allowance) {
+        assert!(
+            self.members.get(member).is_some(),
+            "allowance can be granted only to a member"
+        );
+        if let Some(old) = self.allowances.insert(member, &a) {
+            self.reserved -= old.amount;
+        }
+        self.reserved += a.amount;
+        events::treasury("allowance_granted", None, member, a.amount);
+    }
+
+    /// Removes the member allowance, if any, and releases its remaining funds.
+    pub(crate) fn remove_allowance(&mut self, member: &AccountId) {
+        if let Some(a) = self.allowances.remove(member) {
+            self.reserved -= a.amount;
+            events::treasury("allowance_released", None, member, a.amount);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, StorageUsage};
+
+use crate::proposal::Proposal;
+use crate::{events, Contract, STORAGE_PRICE_PER_BYTE};
+
+/// Entry of the proposal voters index: the account whose vote (or vote commit) is stored
+/// and the account which paid for its storage.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct VoterEntry {
+    pub voter: AccountId,
+    /// `None` when the storage was sponsored from the contract balance.
+    pub payer: Option<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Archives a finalized (executed or expired) proposal: replaces its description with the
+    description hash and removes its votes, unrevealed vote commits and multi-option
+    rankings. Tallies and the outcome, including the executed option, are kept. Funds committed to an expired proposal are released.
+    Freed storage is refunded to whoever paid for it: the proposer for the description and
+    the account which paid for each vote (the voter, its delegate or a relayer). Refunds are
+    credited to the storage balance when registered, transferred otherwise. Storage of
+    sponsored votes returns to the treasury.
+    At most `limit` votes are removed in one call, so archiving a proposal with many votes
+    takes several calls. Anyone can call this function.
+    Returns the number of votes left to remove. */
+    pub fn archive_proposal(&mut self, proposal_id: u32, limit: u32) -> u32 {
+        let idx: u64 = proposal_id.into();
+        let mut p = self.get_proposal(proposal_id);
+        assert!(
+            p.description_hash.is_none() || p.voters > 0,
+            "proposal already archived"
+        );
+        assert!(p.is_finalized(), "proposal is not finalized");
+
+        if p.description_hash.is_none() {
+            let storage_start = env::storage_usage();
+            p.description_hash = Some(env::sha256(p.description.as_bytes()));
+            p.description = String::new();
+            self.proposals.replace(idx, &p);
+            self.refund_freed(&p.proposer, storage_start);
+            self.release_commitment(proposal_id);
+            events::proposal_archived(proposal_id);
+        }
+
+        let end = p.voters.saturating_sub(limit);
+        for n in (end..p.voters).rev() {
+            let storage_start = env::storage_usage();
+            let e = self.vote_index.remove(&(proposal_id, n)).unwrap();
+            let key = (proposal_id, e.voter);
+            // unrevealed secret votes and multi-option rankings are removed as well.
+            self.votes.remove(&key);
+            self.commits.remove(&key);
+            self.ballots.remove(&key);
+            if let Some(payer) = e.payer {
+                self.refund_freed(&payer, storage_start);
+            }
+        }
+        if end < p.voters {
+            events::votes_archived(proposal_id, p.voters - end);
+            p.voters = end;
+            self.proposals.replace(idx, &p);
+        }
+        p.voters
+    }
+}
+
+impl Contract {
+    /// Adds the `voter` to the proposal voters index, with the caller as the storage payer.
+    /// The caller must store the updated proposal.
+    pub(crate) fn index_voter(&mut self, proposal_id: u32, p: &mut Proposal, voter: &AccountId) {
+        self.vote_index.insert(
+            &(proposal_id, p.voters),
+            &VoterEntry {
+                voter: voter.clone(),
+                payer: Some(env::predecessor_account_id()),
+            },
+        );
+        p.voters += 1;
+    }
+
+    /// Refunds the cost of storage freed since `storage_start` to `account`.
+    pub(crate) fn refund_freed(&mut self, account: &AccountId, storage_start: StorageUsage) {
+        let current = env::storage_usage();
+        if current < storage_start {
+            let amount = Balance::from(storage_start - current) * STORAGE_PRICE_PER_BYTE;
+            self.storage_refund(account, amount);
+        }
+    }
+}
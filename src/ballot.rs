@@ -0,0 +1,137 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Secret ballots: votes are committed as hashes during the voting and revealed after it.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, ValidAccountId};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::proposal::FROM_NANO;
+use crate::quadratic::Tally;
+use crate::{events, Contract};
+
+/// Minimum salt length in bytes. Shorter salts let anyone recover the vote from the commit
+/// hash by brute force.
+pub const MIN_SALT_LEN: usize = 32;
+
+/// Committed secret vote.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct BallotCommit {
+    /// `ballot_hash` of the vote.
+    pub hash: Vec<u8>,
+    /// voter power at the commit time.
+    pub power: u32,
+    /// true if the commit storage was sponsored from the contract balance.
+    pub sponsored: bool,
+}
+
+/// Returns sha256 of the proposal id (4 bytes, little endian), the voter account id, the vote
+/// byte (1 for yes, 0 for no) and the salt. The proposal id and the voter bind the commit, so
+/// it can't be copied by another voter nor reused for another proposal.
+/// Panics if the salt is shorter than `MIN_SALT_LEN`.
+pub fn ballot_hash(proposal_id: u32, voter: &AccountId, support: bool, salt: &[u8]) -> Vec<u8> {
+    assert!(
+        salt.len() >= MIN_SALT_LEN,
+        "salt must have at least {} bytes",
+        MIN_SALT_LEN
+    );
+    let mut data = Vec::with_capacity(4 + voter.len() + 1 + salt.len());
+    data.extend_from_slice(&proposal_id.to_le_bytes());
+    data.extend_from_slice(voter.as_bytes());
+    data.push(support as u8);
+    data.extend_from_slice(salt);
+    env::sha256(&data)
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Commits a secret vote for a proposal with secret ballots. `hash` is
+    `sha256(proposal_id || voter || vote || salt)`, where `proposal_id` is 4 little endian
+    bytes, `voter` is the caller account id and `vote` is a single byte: 1 for yes, 0 for no.
+    Use a random salt of at least 32 bytes and keep it to reveal the vote with `reveal_vote`
+    after the voting ends. The voting power is taken at the commit time.
+    Delegations and the quadratic tally don't apply to secret ballots.
+    The caller pays the commit storage, like in `vote`. */
+    #[payable]
+    pub fn commit_vote(&mut self, proposal_id: u32, hash: Base64VecU8) {
+        let a = env::predecessor_account_id();
+        let mut p = self.get_proposal(proposal_id);
+        assert!(
+            p.reveal_end.is_some(),
+            "proposal doesn't use secret ballots"
+        );
+        let t: u64 = env::block_timestamp() / FROM_NANO;
+        assert!(
+            p.voting_start <= t && p.voting_end >= t,
+            "voting is not active"
+        );
+        let hash: Vec<u8> = hash.into();
+        assert!(hash.len() == 32, "hash must be a sha256 hash");
+        assert!(
+            matches!(self.policy(p.policy_version).tally, Tally::Linear),
+            "secret ballots support only the linear tally"
+        );
+        let key = (proposal_id, a.clone());
+        assert!(self.commits.get(&key).is_none(), "you already voted");
+        let storage_start = env::storage_usage();
+        let power = self.take_voting_power(&a, &p);
+        assert!(power > 0, "you ({}) are not authorized to vote", a);
+        let mut commit = BallotCommit {
+            hash,
+            power,
+            sponsored: false,
+        };
+        self.commits.insert(&key, &commit);
+        let first = p.voters;
+        self.index_voter(proposal_id, &mut p, &a);
+        self.proposals.replace(proposal_id.into(), &p);
+        events::vote_committed(proposal_id, &a);
+        if self.sponsor_vote_storage(&a, proposal_id, first..p.voters, storage_start) {
+            commit.sponsored = true;
+            self.commits.insert(&key, &commit);
+        } else {
+            self.refund_storage(storage_start, false);
+        }
+    }
+
+    /// Reveals the caller secret vote committed with `commit_vote` and adds it to the tally.
+    /// Votes can be revealed after the voting ends, until the proposal reveal end. The salt
+    /// must have at least `MIN_SALT_LEN` bytes.
+    /// The freed commit storage is refunded to the caller, or kept in the treasury when
+    /// the commit was sponsored.
+    pub fn reveal_vote(&mut self, proposal_id: u32, support: bool, salt: Base64VecU8) {
+        let a = env::predecessor_account_id();
+        let idx: u64 = proposal_id.into();
+        let mut p = self.get_proposal(proposal_id);
+        let key = (proposal_id, a.clone());
+        let storage_start = env::storage_usage();
+        let commit = self.commits.remove(&key).expect("vote commit not found");
+        assert!(
+            commit.hash == ballot_hash(proposal_id, &a, support, &salt.0),
+            "vote doesn't match the commit"
+        );
+        let vote = p.reveal(commit.power, support);
+        self.votes.insert(&key, &vote);
+        self.proposals.replace(idx, &p);
+        self.update_commitment(proposal_id, &p);
+        events::vote_cast(proposal_id, &a, support, vote.power);
+        if !commit.sponsored {
+            self.refund_storage(storage_start, false);
+        }
+    }
+
+    /// Returns the `voter` hash to commit with `commit_vote`. The hash can be computed
+    /// off-chain as well, which doesn't expose the salt to the RPC node.
+    pub fn ballot_hash(
+        &self,
+        proposal_id: u32,
+        voter: ValidAccountId,
+        support: bool,
+        salt: Base64VecU8,
+    ) -> Base64VecU8 {
+        ballot_hash(proposal_id, voter.as_ref(), support, &salt.0).into()
+    }
+}
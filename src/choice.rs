@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Multi-option proposals: members rank candidate actions and the winning option is
+//! executed.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen};
+
+use crate::proposal::{ActionInt, Proposal, Vote};
+use crate::quadratic::Tally;
+use crate::{events, Contract};
+
+/// Maximum number of options of a multi-option proposal.
+pub const MAX_OPTIONS: usize = 10;
+
+/// Ranking of options (indexes, the most preferred first) with the sum of power of voters
+/// who chose it.
+pub type Ballot = (Vec<u8>, u32);
+
+/// Ranking of options (indexes, the most preferred first) chosen by a voter.
+pub type Ranking = Vec<u8>;
+
+/// Method of choosing the winning option.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum ChoiceMethod {
+    /// The option with the most support wins. Voters choose one option.
+    Plurality,
+    /// Instant runoff: voters rank options, the option with the least first-choice support
+    /// is eliminated and its ballots move to the next ranked option, until an option has
+    /// the majority of the remaining ballots.
+    RankedChoice,
+}
+
+/// Panics if the options are not valid.
+pub fn assert_valid_options(options: &[ActionInt]) {
+    assert!(
+        options.len() >= 2 && options.len() <= MAX_OPTIONS,
+        "multi-option proposal must have between 2 and {} options",
+        MAX_OPTIONS
+    );
+    for o in options {
+        assert!(
+            !matches!(o, ActionInt::Choice { .. }),
+            "options can't be multi-option actions"
+        );
+        o.validate();
+    }
+}
+
+/// Panics if the ranking is not valid for the proposal options.
+fn assert_valid_ranking(method: ChoiceMethod, options: usize, ranking: &[u8]) {
+    match method {
+        ChoiceMethod::Plurality => assert!(
+            ranking.len() == 1,
+            "plurality vote must choose exactly one option"
+        ),
+        ChoiceMethod::RankedChoice => assert!(!ranking.is_empty(), "ranking is empty"),
+    }
+    for (i, o) in ranking.iter().enumerate() {
+        assert!(usize::from(*o) < options, "option {} not found", o);
+        assert!(!ranking[..i].contains(o), "option {} ranked twice", o);
+    }
+}
+
+/**
+Returns the winning option index of a multi-option proposal or `None` if there is no winner.
+`ballots` are voters rankings with the sum of their power. The winner must have at least
+`min_support` power in the deciding round. A tie has no winner. */
+pub fn winner(
+    method: ChoiceMethod,
+    options: usize,
+    ballots: &[Ballot],
+    min_support: u32,
+) -> Option<usize> {
+    let mut eliminated = vec![false; options];
+    loop {
+        let mut counts = vec![0u32; options];
+        for (ranking, power) in ballots {
+            let top = match method {
+                ChoiceMethod::Plurality => ranking.first(),
+                ChoiceMethod::RankedChoice => ranking.iter().find(|o| !eliminated[**o as usize]),
+            };
+            if let Some(o) = top {
+                counts[*o as usize] += power;
+            }
+        }
+        let remaining: Vec<usize> = (0..options).filter(|o| !eliminated[*o]).collect();
+        let total: u32 = counts.iter().sum();
+        let max = remaining.iter().map(|o| counts[*o]).max().unwrap_or(0);
+        let leaders: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|o| counts[*o] == max)
+            .collect();
+        let decided = match method {
+            ChoiceMethod::Plurality => true,
+            ChoiceMethod::RankedChoice => 2 * max > total || leaders.len() == remaining.len(),
+        };
+        if decided {
+            return if leaders.len() == 1 && max >= min_support {
+                Some(leaders[0])
+            } else {
+                None
+            };
+        }
+        let min = remaining.iter().map(|o| counts[*o]).min().unwrap();
+        for o in remaining {
+            if counts[o] == min {
+                eliminated[o] = true;
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Votes on a multi-option proposal. `ranking` lists option indexes, the most preferred
+    first. Plurality proposals accept exactly one option. Delegations and the quadratic
+    tally don't apply to multi-option proposals.
+    The caller pays the vote storage, like in `vote`. */
+    #[payable]
+    pub fn vote_option(&mut self, proposal_id: u32, ranking: Vec<u8>) {
+        let a = env::predecessor_account_id();
+        let idx: u64 = proposal_id.into();
+        let mut p = self.get_proposal(proposal_id);
+        let (method, options) = match &p.action {
+            ActionInt::Choice { options, method } => (*method, options.len()),
+            _ => env::panic(b"proposal is not a multi-option proposal"),
+        };
+        assert_valid_ranking(method, options, &ranking);
+        assert!(
+            matches!(self.policy(p.policy_version).tally, Tally::Linear),
+            "multi-option proposals support only the linear tally"
+        );
+        let storage_start = env::storage_usage();
+        let power = self.take_voting_power(&a, &p);
+        assert!(power > 0, "you ({}) are not authorized to vote", a);
+        let key = (proposal_id, a.clone());
+        assert!(self.votes.get(&key).is_none(), "you already voted");
+        let first = p.voters;
+        p.vote_option(power);
+        self.ballots.insert(&key, &ranking);
+        self.votes.insert(
+            &key,
+            &Vote {
+                support: None,
+                power,
+                delegated: false,
+            },
+        );
+        self.index_voter(proposal_id, &mut p, &a);
+        self.proposals.replace(idx, &p);
+        self.update_commitment(proposal_id, &p);
+        events::option_vote_cast(proposal_id, &a, &ranking, power);
+        if !self.sponsor_vote_storage(&a, proposal_id, first..p.voters, storage_start) {
+            self.refund_storage(storage_start, false);
+        }
+    }
+
+    /// Returns rankings of a multi-option proposal with the sum of the voters power.
+    /// Rankings are removed when the proposal is archived.
+    pub fn proposal_ballots(&self, proposal_id: u32) -> Vec<Ballot> {
+        self.choice_ballots(proposal_id, &self.get_proposal(proposal_id))
+    }
+}
+
+impl Contract {
+    /// Returns rankings of the proposal voters with the sum of power of voters who chose
+    /// each of them. Empty if it's not a multi-option proposal.
+    pub(crate) fn choice_ballots(&self, proposal_id: u32, p: &Proposal) -> Vec<Ballot> {
+        let mut ballots: Vec<Ballot> = Vec::new();
+        if !matches!(p.action, ActionInt::Choice { .. }) {
+            return ballots;
+        }
+        for n in 0..p.voters {
+            let key = (
+                proposal_id,
+                self.vote_index.get(&(proposal_id, n)).unwrap().voter,
+            );
+            let (ranking, vote) = match (self.ballots.get(&key), self.votes.get(&key)) {
+                (Some(r), Some(v)) => (r, v),
+                _ => continue,
+            };
+            match ballots.iter_mut().find(|b| b.0 == ranking) {
+                Some(b) => b.1 += vote.power,
+                None => ballots.push((ranking, vote.power)),
+            }
+        }
+        ballots
+    }
+}
@@ -2,11 +2,18 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashSet;
+
 use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::AccountId;
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::conviction::ConvictionParams;
+use crate::proposal::Voter;
+use crate::{Contract, STORAGE_PRICE_PER_BYTE};
 
-// use crate::Voter;
+/// Maximum voting power of a member.
+pub const MAX_POWER: u16 = 10000;
 
 /// Contract settings
 #[derive(Serialize, Deserialize)]
@@ -14,9 +21,93 @@ use near_sdk::AccountId;
 pub struct Settings {
     pub deployer_id: AccountId,
     pub members: String, // Vec<Voter>,
+    /// sum of members power
+    pub total_power: u32,
     pub min_support: u32,
     pub min_duration: u32,
     pub max_duration: u32,
     pub min_bond: U128,
+    /// vote storage paid from the contract balance per member in each period (zero if
+    /// disabled)
+    pub vote_sponsorship_cap: U128,
+    /// sponsorship period in seconds
+    pub vote_sponsorship_period: u32,
+    /// objection power rejecting optimistic proposals (zero if disabled)
+    pub objection_threshold: u32,
+    /// maximum amount transferred by an optimistic proposal (zero if disabled)
+    pub optimistic_max_amount: U128,
+    /// conviction voting parameters (`None` if disabled)
+    pub conviction: Option<ConvictionParams>,
     pub unix_time: U64,
 }
+
+#[near_bindgen]
+impl Contract {
+    /// Runs the `new` checks on the given configuration and returns the list of problems.
+    /// Empty list means the configuration is valid. The view doesn't read the contract state.
+    pub fn validate_config(
+        members: Vec<Voter>,
+        min_support: u32,
+        min_duration: u32,
+        max_duration: u32,
+        min_bond: U128,
+    ) -> Vec<String> {
+        config_errors(
+            &members,
+            min_support,
+            min_duration,
+            max_duration,
+            min_bond.into(),
+        )
+    }
+}
+
+/// Returns the list of problems of the contract configuration.
+pub fn config_errors(
+    members: &[Voter],
+    min_support: u32,
+    min_duration: u32,
+    max_duration: u32,
+    min_bond: Balance,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    if min_support == 0 {
+        errors.push("min_support must be positive".to_string());
+    }
+    if min_duration < 2 || max_duration <= min_duration {
+        errors.push("min_duration and max_duration must be at least 2".to_string());
+    }
+    if min_bond <= STORAGE_PRICE_PER_BYTE {
+        errors.push(format!(
+            "min_bond must be bigger than {}",
+            STORAGE_PRICE_PER_BYTE
+        ));
+    }
+    if members.is_empty() {
+        errors.push("members list must not be empty".to_string());
+    }
+    let mut accounts = HashSet::new();
+    let mut total_power: u32 = 0;
+    for v in members {
+        if !env::is_valid_account_id(v.account.as_bytes()) {
+            errors.push(format!("account {} is not valid", v.account));
+        }
+        if !accounts.insert(&v.account) {
+            errors.push(format!("member {} is duplicated", v.account));
+        }
+        if v.power == 0 || v.power > MAX_POWER {
+            errors.push(format!(
+                "member {} power must be between 1 and {}",
+                v.account, MAX_POWER
+            ));
+        }
+        total_power += u32::from(v.power);
+    }
+    if !members.is_empty() && min_support > total_power {
+        errors.push(format!(
+            "min_support ({}) is bigger than the total members power ({})",
+            min_support, total_power
+        ));
+    }
+    errors
+}
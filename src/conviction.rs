@@ -0,0 +1,411 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Conviction voting for continuous funding. Members stake their power on funding requests,
+//! the request conviction grows with the time the power stays staked and the request can
+//! be executed once its conviction reaches a threshold, which grows with the requested
+//! share of the treasury.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::proposal::FROM_NANO;
+use crate::treasury::{mul_div, U256};
+use crate::{events, Contract};
+
+/// Fixed-point scale of conviction computations.
+pub const SCALE: u128 = 1_000_000_000_000;
+const PPM: u128 = 1_000_000;
+const BPS: u128 = 10_000;
+
+/// Conviction voting parameters.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConvictionParams {
+    /// conviction period in seconds, conviction is updated once per period.
+    pub period: u32,
+    /// part of the conviction kept after each period, in parts per million.
+    pub decay: u32,
+    /// maximum share of the available balance a request can ask for, in basis points.
+    pub max_share: u32,
+    /// part of the total power which must be staked to fund the smallest requests, in
+    /// parts per million.
+    pub weight: u32,
+}
+
+impl ConvictionParams {
+    /// Panics if the parameters are not valid.
+    pub fn validate(&self) {
+        assert!(self.period > 0, "conviction period must be positive");
+        assert!(
+            self.decay > 0 && u128::from(self.decay) < PPM,
+            "decay must be between 1 and {}",
+            PPM - 1
+        );
+        assert!(
+            self.max_share > 0 && u128::from(self.max_share) <= BPS,
+            "max_share must be between 1 and {}",
+            BPS
+        );
+        assert!(self.weight > 0, "weight must be positive");
+    }
+
+    fn alpha(&self) -> u128 {
+        u128::from(self.decay) * (SCALE / PPM)
+    }
+
+    /// Returns the conviction (scaled by `SCALE`) after `periods` with `staked` power,
+    /// starting from `conviction`: `y(n) = alpha^n * y(0) + staked * (1 - alpha^n) / (1 - alpha)`.
+    pub fn grow(&self, conviction: u128, staked: u32, periods: u64) -> u128 {
+        let alpha = self.alpha();
+        let alpha_n = pow(alpha, periods);
+        mul_div(conviction, alpha_n, SCALE)
+            + mul_div(u128::from(staked) * SCALE, SCALE - alpha_n, SCALE - alpha)
+    }
+
+    /// Returns the conviction (scaled by `SCALE`) required to fund `amount` from `treasury`:
+    /// `weight * total_power / (1 - alpha) / (max_share - amount / treasury)^2`.
+    /// Returns `None` if the request exceeds the maximum share.
+    pub fn threshold(&self, amount: Balance, treasury: Balance, total_power: u32) -> Option<u128> {
+        if treasury == 0 {
+            return None;
+        }
+        let share = U256::from(amount) * U256::from(SCALE) / U256::from(treasury);
+        let max_share = U256::from(u128::from(self.max_share) * SCALE / BPS);
+        if share >= max_share {
+            return None;
+        }
+        let d = max_share - share;
+        let t = U256::from(u128::from(self.weight) * (SCALE / PPM))
+            * U256::from(total_power)
+            * U256::from(SCALE)
+            / U256::from(SCALE - self.alpha())
+            * U256::from(SCALE)
+            * U256::from(SCALE)
+            / (d * d);
+        Some(if t > U256::from(u128::MAX) {
+            u128::MAX
+        } else {
+            t.as_u128()
+        })
+    }
+}
+
+/// Returns `base^n`, where `base` and the result are scaled by `SCALE`.
+fn pow(mut base: u128, mut n: u64) -> u128 {
+    let mut r = SCALE;
+    while n > 0 && r > 0 {
+        if n & 1 == 1 {
+            r = r * base / SCALE;
+        }
+        base = base * base / SCALE;
+        n >>= 1;
+    }
+    r
+}
+
+/// Request to fund `recipient` from the treasury with conviction voting.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FundingRequest {
+    pub proposer: AccountId,
+    pub recipient: AccountId,
+    pub amount: Balance,
+    pub description: String,
+    /// conviction (scaled by `SCALE`) at `updated`.
+    pub conviction: u128,
+    /// sum of the power staked on the request.
+    pub staked: u32,
+    /// block timestamp in seconds of the last conviction update.
+    pub updated: u64,
+    pub executed: bool,
+    /// set when the proposer cancels the request.
+    pub cancelled: bool,
+}
+
+impl FundingRequest {
+    /// Returns true if the request is executed or cancelled. Stakes on closed requests
+    /// are released.
+    fn is_closed(&self) -> bool {
+        self.executed || self.cancelled
+    }
+
+    fn assert_open(&self) {
+        assert!(!self.executed, "funding request already executed");
+        assert!(!self.cancelled, "funding request cancelled");
+    }
+
+    /// Updates the conviction with the periods passed since the last update.
+    fn update_conviction(&mut self, params: &ConvictionParams) {
+        let t = env::block_timestamp() / FROM_NANO;
+        let periods = (t - self.updated) / u64::from(params.period);
+        self.conviction = params.grow(self.conviction, self.staked, periods);
+        self.updated += periods * u64::from(params.period);
+    }
+
+    /// Records the conviction before a change of the staked power, so the new stake doesn't
+    /// count for the time before the change. While conviction voting is disabled, the
+    /// conviction doesn't grow and only the update time moves.
+    fn checkpoint(&mut self, params: Option<&ConvictionParams>) {
+        match params {
+            Some(params) => self.update_conviction(params),
+            None => self.updated = env::block_timestamp() / FROM_NANO,
+        }
+    }
+}
+
+/// JSON compatible return type for FundingRequest.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct FundingRequestOut {
+    pub proposer: AccountId,
+    pub recipient: AccountId,
+    pub amount: U128,
+    pub description: String,
+    /// current conviction, scaled by 10^12.
+    pub conviction: U128,
+    /// conviction required to execute the request with the current available balance,
+    /// scaled by 10^12. `None` if the request exceeds the maximum treasury share or conviction
+    /// voting is disabled.
+    pub threshold: Option<U128>,
+    pub staked: u32,
+    pub executed: bool,
+    pub cancelled: bool,
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Adds a request to fund `recipient` with `amount` from the treasury. Members stake their
+    power on the request with `stake_conviction` and anyone can execute it once its
+    conviction reaches the threshold.
+    The caller has to provide a deposit = max(self.min_bond, this_tx_storage_cost). */
+    #[payable]
+    pub fn add_funding_request(
+        &mut self,
+        recipient: ValidAccountId,
+        amount: U128,
+        description: String,
+    ) -> u32 {
+        assert!(
+            self.conviction_params.is_some(),
+            "conviction voting is disabled"
+        );
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "amount must be positive");
+        let storage_start = env::storage_usage();
+        let r = FundingRequest {
+            proposer: env::predecessor_account_id(),
+            recipient: recipient.into(),
+            amount,
+            description,
+            conviction: 0,
+            staked: 0,
+            updated: env::block_timestamp() / FROM_NANO,
+            executed: false,
+            cancelled: false,
+        };
+        self.funding_requests.push(&r);
+        let id = (self.funding_requests.len() - 1) as u32;
+        events::treasury("funding_request_created", Some(id), &r.recipient, amount);
+        self.refund_storage(storage_start, true);
+        id
+    }
+
+    /**
+    Sets the caller power staked on the funding request, zero removes the stake. The sum of
+    the caller stakes on open requests can't be bigger than the caller current
+    power. When a `SetMember` proposal lowers the power below the stakes, the stakes are
+    scaled down and removing the member drops them.
+    The caller pays the storage cost. */
+    #[payable]
+    pub fn stake_conviction(&mut self, request_id: u32, power: u32) {
+        let a = env::predecessor_account_id();
+        let idx: u64 = request_id.into();
+        let mut r = self
+            .funding_requests
+            .get(idx)
+            .expect("request_id not found");
+        r.assert_open();
+        let storage_start = env::storage_usage();
+        let mut stakes = self.conviction_stakes.get(&a).unwrap_or_default();
+        let prev = stakes
+            .iter()
+            .find(|(id, _)| *id == request_id)
+            .map_or(0, |(_, p)| *p);
+        // stakes on closed requests are released.
+        stakes.retain(|(id, _)| {
+            *id != request_id
+                && !self
+                    .funding_requests
+                    .get(u64::from(*id))
+                    .unwrap()
+                    .is_closed()
+        });
+        let required: u32 = stakes.iter().map(|(_, p)| p).sum::<u32>() + power;
+        let available = u32::from(self.power_at(&a, self.policy_version()));
+        assert!(
+            required <= available,
+            "not enough power to stake (available: {}, required: {})",
+            available,
+            required
+        );
+        if power > 0 {
+            stakes.push((request_id, power));
+        }
+        if stakes.is_empty() {
+            self.conviction_stakes.remove(&a);
+        } else {
+            self.conviction_stakes.insert(&a, &stakes);
+        }
+        r.checkpoint(self.conviction_params.as_ref());
+        r.staked = r.staked - prev + power;
+        self.funding_requests.replace(idx, &r);
+        events::conviction_staked(request_id, &a, power);
+        self.refund_storage(storage_start, false);
+    }
+
+    /// Executes the funding request if its conviction reached the threshold, transferring
+    /// the requested amount to the recipient. Funds committed to passing proposals and
+    /// reserved funds are not available to funding requests.
+    /// Anyone can call this function.
+    pub fn execute_funding_request(&mut self, request_id: u32) -> Promise {
+        let params = self
+            .conviction_params
+            .clone()
+            .expect("conviction voting is disabled");
+        let idx: u64 = request_id.into();
+        let mut r = self
+            .funding_requests
+            .get(idx)
+            .expect("request_id not found");
+        r.assert_open();
+        r.update_conviction(&params);
+        let threshold = params
+            .threshold(r.amount, self.available(None), self.total_power)
+            .expect("funding request exceeds the maximum share of the treasury");
+        assert!(
+            r.conviction >= threshold,
+            "not enough conviction (conviction: {}, threshold: {})",
+            r.conviction,
+            threshold
+        );
+        self.assert_available(r.amount, None);
+        r.executed = true;
+        self.funding_requests.replace(idx, &r);
+        events::treasury(
+            "funding_request_executed",
+            Some(request_id),
+            &r.recipient,
+            r.amount,
+        );
+        Promise::new(r.recipient).transfer(r.amount)
+    }
+
+    /// Cancels the funding request, so it can't be executed, and releases the stakes on it.
+    /// Can be called only by the request proposer. Requires exactly 1 yocto NEAR attached.
+    #[payable]
+    pub fn cancel_funding_request(&mut self, request_id: u32) {
+        assert_one_yocto();
+        let idx: u64 = request_id.into();
+        let mut r = self
+            .funding_requests
+            .get(idx)
+            .expect("request_id not found");
+        assert!(
+            env::predecessor_account_id() == r.proposer,
+            "only the proposer ({}) can cancel the funding request",
+            r.proposer
+        );
+        r.assert_open();
+        r.cancelled = true;
+        self.funding_requests.replace(idx, &r);
+        events::treasury(
+            "funding_request_cancelled",
+            Some(request_id),
+            &r.recipient,
+            0,
+        );
+    }
+
+    /// Returns the funding request by id with its current conviction.
+    /// Panics when `request_id` is not found.
+    pub fn funding_request(&self, request_id: u32) -> FundingRequestOut {
+        let mut r = self
+            .funding_requests
+            .get(request_id.into())
+            .expect("request_id not found");
+        let threshold = self.conviction_params.as_ref().and_then(|params| {
+            r.update_conviction(params);
+            params.threshold(r.amount, self.available(None), self.total_power)
+        });
+        FundingRequestOut {
+            proposer: r.proposer,
+            recipient: r.recipient,
+            amount: r.amount.into(),
+            description: r.description,
+            conviction: r.conviction.into(),
+            threshold: threshold.map(U128::from),
+            staked: r.staked,
+            executed: r.executed,
+            cancelled: r.cancelled,
+        }
+    }
+
+    /// Returns the member stakes as (request_id, power) pairs.
+    pub fn conviction_stakes(&self, member: ValidAccountId) -> Vec<(u32, u32)> {
+        self.conviction_stakes
+            .get(member.as_ref())
+            .unwrap_or_default()
+    }
+}
+
+impl Contract {
+    /// Fits the member stakes on open requests into the new member `power`:
+    /// when their sum is bigger, each stake is scaled down proportionally. Zero power drops
+    /// all stakes.
+    pub(crate) fn rescale_conviction_stakes(&mut self, member: &AccountId, power: u16) {
+        let stakes = match self.conviction_stakes.get(member) {
+            Some(s) => s,
+            None => return,
+        };
+        // stakes on closed requests are released.
+        let stakes: Vec<(u32, u32)> = stakes
+            .into_iter()
+            .filter(|(id, _)| {
+                !self
+                    .funding_requests
+                    .get(u64::from(*id))
+                    .unwrap()
+                    .is_closed()
+            })
+            .collect();
+        let total: u32 = stakes.iter().map(|(_, p)| p).sum();
+        let power = u32::from(power);
+        if total <= power {
+            return;
+        }
+        let mut kept = Vec::new();
+        for (id, prev) in stakes {
+            let staked = (u64::from(prev) * u64::from(power) / u64::from(total)) as u32;
+            let idx: u64 = id.into();
+            let mut r = self.funding_requests.get(idx).unwrap();
+            r.checkpoint(self.conviction_params.as_ref());
+            r.staked = r.staked - prev + staked;
+            self.funding_requests.replace(idx, &r);
+            events::conviction_staked(id, member, staked);
+            if staked > 0 {
+                kept.push((id, staked));
+            }
+        }
+        if kept.is_empty() {
+            self.conviction_stakes.remove(member);
+        } else {
+            self.conviction_stakes.insert(member, &kept);
+        }
+    }
+}
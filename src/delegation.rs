@@ -0,0 +1,179 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::proposal::{Proposal, FROM_NANO};
+use crate::{events, Contract};
+
+/// Maximum number of proposals checked in one call when advancing the closed voting mark.
+const MAX_CLOSED_SCAN: u32 = 10;
+
+/// Member who delegated the voting power. The delegation applies to proposals with id in
+/// `[since, until)`, so a change of the delegation doesn't affect existing proposals.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Delegator {
+    pub account: AccountId,
+    pub since: u32,
+    /// `None` while the delegation is active.
+    pub until: Option<u32>,
+}
+
+impl Delegator {
+    /// Returns true if the delegation applies to the given proposal.
+    pub fn applies(&self, proposal_id: u32) -> bool {
+        self.since <= proposal_id && self.until.map_or(true, |u| proposal_id < u)
+    }
+
+    /// Returns true if the delegation ended and applies only to proposals with id lower
+    /// than `closed_before`.
+    fn expired(&self, closed_before: u32) -> bool {
+        self.until.map_or(false, |u| u <= closed_before)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Delegates the caller voting power to the `to` member. The delegate casts the caller
+    power when voting on proposals created after this call, unless the caller votes directly
+    (before or after the delegate). Replaces the previous delegation.
+    The caller pays the storage cost. */
+    #[payable]
+    pub fn delegate(&mut self, to: ValidAccountId) {
+        let a = env::predecessor_account_id();
+        assert!(self.members.get(&a).is_some(), "only a member can delegate");
+        let to: AccountId = to.into();
+        assert!(to != a, "you can't delegate to yourself");
+        assert!(
+            self.members.get(&to).is_some(),
+            "delegate {} is not a member",
+            to
+        );
+        let storage_start = env::storage_usage();
+        self.end_delegation(&a);
+        let mut delegators = self.delegators.get(&to).unwrap_or_default();
+        self.prune_delegators(&mut delegators);
+        delegators.push(Delegator {
+            account: a.clone(),
+            since: self.next_idx,
+            until: None,
+        });
+        self.delegators.insert(&to, &delegators);
+        self.delegations.insert(&a, &to);
+        events::delegation_changed(&a, Some(&to));
+        self.refund_storage(storage_start, false);
+    }
+
+    /// Ends the caller delegation for proposals created after this call.
+    #[payable]
+    pub fn undelegate(&mut self) {
+        let a = env::predecessor_account_id();
+        let storage_start = env::storage_usage();
+        assert!(self.end_delegation(&a), "you didn't delegate");
+        events::delegation_changed(&a, None);
+        self.refund_storage(storage_start, false);
+    }
+
+    /// Returns the current delegate of the `member`.
+    pub fn delegation(&self, member: ValidAccountId) -> Option<AccountId> {
+        self.delegations.get(member.as_ref())
+    }
+
+    /// Returns members whose power `delegate` casts for the given proposal. Returns
+    /// delegators for new proposals if `proposal_id` is not set.
+    pub fn delegators(&self, delegate: ValidAccountId, proposal_id: Option<u32>) -> Vec<AccountId> {
+        let proposal_id = proposal_id.unwrap_or(self.next_idx);
+        self.delegators
+            .get(delegate.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|d| d.applies(proposal_id))
+            .map(|d| d.account)
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Ends the member delegation for proposals created from now on. Returns false if the
+    /// member doesn't delegate.
+    fn end_delegation(&mut self, member: &AccountId) -> bool {
+        let to = match self.delegations.remove(member) {
+            Some(to) => to,
+            None => return false,
+        };
+        let next = self.next_idx;
+        let mut delegators = self.delegators.get(&to).unwrap();
+        // delegation which didn't apply to any proposal is dropped.
+        delegators.retain(|d| !(d.account == *member && d.until.is_none() && d.since == next));
+        for d in delegators.iter_mut() {
+            if d.account == *member && d.until.is_none() {
+                d.until = Some(next);
+            }
+        }
+        self.prune_delegators(&mut delegators);
+        if delegators.is_empty() {
+            self.delegators.remove(&to);
+        } else {
+            self.delegators.insert(&to, &delegators);
+        }
+        true
+    }
+
+    /**
+    Removes ended delegations which apply only to proposals whose voting is closed, so the
+    history of a delegate holds the active delegations and those ended while a proposal
+    they apply to is still open. */
+    fn prune_delegators(&mut self, delegators: &mut Vec<Delegator>) {
+        self.advance_voting_closed();
+        let closed_before = self.voting_closed_before;
+        delegators.retain(|d| !d.expired(closed_before));
+    }
+
+    /// Moves `voting_closed_before` past proposals whose voting ended, checking at most
+    /// `MAX_CLOSED_SCAN` proposals. It stops at the first proposal still open for voting.
+    fn advance_voting_closed(&mut self) {
+        let t = env::block_timestamp() / FROM_NANO;
+        let end = self
+            .next_idx
+            .min(self.voting_closed_before.saturating_add(MAX_CLOSED_SCAN));
+        while self.voting_closed_before < end
+            && !self
+                .proposals_migration
+                .is_pending(self.voting_closed_before)
+            && self.get_proposal(self.voting_closed_before).voting_end < t
+        {
+            self.voting_closed_before += 1;
+        }
+    }
+
+    /// Casts the power of members who delegated to `delegate` and didn't vote yet.
+    pub(crate) fn vote_delegated(
+        &mut self,
+        proposal_id: u32,
+        p: &mut Proposal,
+        delegate: &AccountId,
+        vote_yes: bool,
+    ) {
+        let delegators = match self.delegators.get(delegate) {
+            Some(d) => d,
+            None => return,
+        };
+        for d in delegators.into_iter().filter(|d| d.applies(proposal_id)) {
+            let key = (proposal_id, d.account);
+            if self.votes.get(&key).is_some() {
+                continue;
+            }
+            let power = self.take_voting_power(&key.1, p);
+            if power > 0 {
+                let vote = p.vote_delegated(power, vote_yes);
+                self.votes.insert(&key, &vote);
+                self.index_voter(proposal_id, p, &key.1);
+                events::delegated_vote_cast(proposal_id, &key.1, delegate, vote_yes, vote.power);
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashSet;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise, PromiseOrValue};
+
+use crate::{events, Contract};
+
+/// Part of an escrow released to the beneficiary once confirmed by members.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Milestone {
+    pub name: String,
+    pub amount: Balance,
+    /// members who confirmed the milestone
+    pub confirmations: HashSet<AccountId>,
+    /// sum of power of members who confirmed the milestone
+    pub support: u32,
+    pub released: bool,
+}
+
+/// Funds locked for a beneficiary and released in milestones.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Escrow {
+    pub beneficiary: AccountId,
+    pub milestones: Vec<Milestone>,
+    /// minimum support (in power) to release a milestone
+    pub release_support: u32,
+    /// Policy version at the escrow creation. Milestones are confirmed by members of this
+    /// version with their power in it, later membership changes don't apply.
+    pub policy_version: u32,
+    pub cancelled: bool,
+}
+
+impl Escrow {
+    /// Returns the amount of not released milestones.
+    pub fn locked(&self) -> Balance {
+        self.milestones
+            .iter()
+            .filter(|m| !m.released)
+            .map(|m| m.amount)
+            .sum()
+    }
+}
+
+/// NewMilestone is an input to create a `Milestone`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Clone, Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct NewMilestone {
+    pub name: String,
+    pub amount: U128,
+}
+
+/// Asserts that escrow parameters are valid.
+pub fn assert_valid_escrow(milestones: &[(String, Balance)], release_support: u32) {
+    assert!(!milestones.is_empty(), "escrow must have milestones");
+    for (name, amount) in milestones {
+        assert!(*amount > 0, "milestone {} amount must be positive", name);
+    }
+    assert!(release_support > 0, "release_support must be positive");
+}
+
+/// JSON compatible return type for Milestone.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneOut {
+    pub name: String,
+    pub amount: U128,
+    pub confirmations: Vec<AccountId>,
+    pub support: u32,
+    pub released: bool,
+}
+
+/// JSON compatible return type for Escrow.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowOut {
+    pub beneficiary: AccountId,
+    pub milestones: Vec<MilestoneOut>,
+    pub release_support: u32,
+    pub policy_version: u32,
+    pub cancelled: bool,
+}
+
+impl From<Escrow> for EscrowOut {
+    fn from(e: Escrow) -> EscrowOut {
+        EscrowOut {
+            beneficiary: e.beneficiary,
+            milestones: e
+                .milestones
+                .into_iter()
+                .map(|m| MilestoneOut {
+                    name: m.name,
+                    amount: m.amount.into(),
+                    confirmations: m.confirmations.into_iter().collect(),
+                    support: m.support,
+                    released: m.released,
+                })
+                .collect(),
+            release_support: e.release_support,
+            policy_version: e.policy_version,
+            cancelled: e.cancelled,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Confirms that an escrow milestone is done. Only a member can confirm a milestone and each
+    member can confirm it only once. Members and their power are taken from the policy
+    version of the escrow creation: members added later can't confirm and removed members
+    still can. Once the milestone confirmations reach the escrow `release_support`, the
+    milestone amount is transferred to the beneficiary.
+    The caller has to attach a deposit to cover the storage cost, the change is refunded. */
+    #[payable]
+    pub fn confirm_milestone(&mut self, escrow_id: u32, milestone: u32) -> PromiseOrValue<()> {
+        let a = env::predecessor_account_id();
+        let idx: u64 = escrow_id.into();
+        let mut e = self.escrows.get(idx).expect("escrow_id not found");
+        let power = self.power_at(&a, e.policy_version);
+        assert!(power > 0, "you ({}) are not authorized to confirm", a);
+        assert!(!e.cancelled, "escrow is cancelled");
+        let release_support = e.release_support;
+        let m = e
+            .milestones
+            .get_mut(milestone as usize)
+            .expect("milestone not found");
+        assert!(!m.released, "milestone already released");
+        let storage_start = env::storage_usage();
+        assert!(m.confirmations.insert(a.clone()), "you already confirmed");
+        m.support += u32::from(power);
+        events::milestone_confirmed(escrow_id, milestone, &a, m.support);
+        let released = m.support >= release_support;
+        let amount = m.amount;
+        if released {
+            m.released = true;
+            self.reserved -= amount;
+            events::treasury(
+                "milestone_released",
+                Some(escrow_id),
+                &e.beneficiary,
+                amount,
+            );
+        }
+        let beneficiary = e.beneficiary.clone();
+        self.escrows.replace(idx, &e);
+        self.refund_storage(storage_start, false);
+        if released {
+            Promise::new(beneficiary).transfer(amount).into()
+        } else {
+            PromiseOrValue::Value(())
+        }
+    }
+
+    /// Returns escrow by id.
+    /// Panics when `escrow_id` is not found.
+    pub fn escrow(&self, escrow_id: u32) -> EscrowOut {
+        self.escrows
+            .get(escrow_id.into())
+            .expect("escrow_id not found")
+            .into()
+    }
+}
+
+impl Contract {
+    /// Creates a new escrow and locks funds for all its milestones.
+    pub(crate) fn create_escrow(&mut self, e: Escrow) {
+        self.reserved += e.locked();
+        self.escrows.push(&e);
+        let id = (self.escrows.len() - 1) as u32;
+        events::treasury("escrow_created", Some(id), &e.beneficiary, e.locked());
+    }
+
+    /// Cancels an escrow and returns funds of not released milestones to the treasury.
+    pub(crate) fn cancel_escrow(&mut self, escrow_id: u32) {
+        let idx: u64 = escrow_id.into();
+        let mut e = self.escrows.get(idx).expect("escrow_id not found");
+        assert!(!e.cancelled, "escrow is cancelled");
+        e.cancelled = true;
+        self.reserved -= e.locked();
+        self.escrows.replace(idx, &e);
+        events::treasury("escrow_cancelled", Some(escrow_id), &e.beneficiary, 0);
+    }
+}
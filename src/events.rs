@@ -0,0 +1,238 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! NEP-297 events. Each event is logged as `EVENT_JSON:` followed by a JSON object with
+//! `standard`, `version`, `event` and `data` (list with one object) fields.
+//! The data schema of an event must not change without bumping `EVENT_VERSION`.
+
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{log, AccountId, Balance};
+use serde_json::{json, Value};
+
+pub const EVENT_STANDARD: &str = "vostok-dao";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    event: &'a str,
+    data: [Value; 1],
+}
+
+/// Logs the `event` with the given `data`.
+pub fn emit(event: &str, data: Value) {
+    let e = EventLog {
+        standard: EVENT_STANDARD,
+        version: EVENT_VERSION,
+        event,
+        data: [data],
+    };
+    log!("EVENT_JSON:{}", serde_json::to_string(&e).unwrap());
+}
+
+pub fn proposal_created(proposal_id: u32, proposer: &AccountId) {
+    emit(
+        "proposal_created",
+        json!({ "proposal_id": proposal_id, "proposer": proposer }),
+    );
+}
+
+pub fn vote_cast(proposal_id: u32, voter: &AccountId, support: bool, power: u32) {
+    emit(
+        "vote_cast",
+        json!({
+            "proposal_id": proposal_id,
+            "voter": voter,
+            "support": support,
+            "power": power,
+        }),
+    );
+}
+
+/// `member` staked `power` on the funding request (zero removes the stake).
+pub fn conviction_staked(request_id: u32, member: &AccountId, power: u32) {
+    emit(
+        "conviction_staked",
+        json!({
+            "request_id": request_id,
+            "member": member,
+            "power": power,
+        }),
+    );
+}
+
+/// `relayer` submitted the `voter` off-chain signed vote.
+pub fn vote_relayed(proposal_id: u32, voter: &AccountId, relayer: &AccountId) {
+    emit(
+        "vote_relayed",
+        json!({
+            "proposal_id": proposal_id,
+            "voter": voter,
+            "relayer": relayer,
+        }),
+    );
+}
+
+/// `voter` committed a secret vote. The vote is cast when revealed.
+pub fn vote_committed(proposal_id: u32, voter: &AccountId) {
+    emit(
+        "vote_committed",
+        json!({
+            "proposal_id": proposal_id,
+            "voter": voter,
+        }),
+    );
+}
+
+/// `voter` ranked the options of a multi-option proposal.
+pub fn option_vote_cast(proposal_id: u32, voter: &AccountId, ranking: &[u8], power: u32) {
+    emit(
+        "option_vote_cast",
+        json!({
+            "proposal_id": proposal_id,
+            "voter": voter,
+            "ranking": ranking,
+            "power": power,
+        }),
+    );
+}
+
+/// `voter` power was cast by the `delegate`.
+pub fn delegated_vote_cast(
+    proposal_id: u32,
+    voter: &AccountId,
+    delegate: &AccountId,
+    support: bool,
+    power: u32,
+) {
+    emit(
+        "delegated_vote_cast",
+        json!({
+            "proposal_id": proposal_id,
+            "voter": voter,
+            "delegate": delegate,
+            "support": support,
+            "power": power,
+        }),
+    );
+}
+
+/// `member` delegated the voting power to `delegate` or ended the delegation (`None`).
+pub fn delegation_changed(member: &AccountId, delegate: Option<&AccountId>) {
+    emit(
+        "delegation_changed",
+        json!({ "member": member, "delegate": delegate }),
+    );
+}
+
+pub fn proposal_executed(proposal_id: u32) {
+    emit("proposal_executed", json!({ "proposal_id": proposal_id }));
+}
+
+/// The proposal action promise failed.
+pub fn execution_failed(proposal_id: u32) {
+    emit("execution_failed", json!({ "proposal_id": proposal_id }));
+}
+
+pub fn proposal_archived(proposal_id: u32) {
+    emit("proposal_archived", json!({ "proposal_id": proposal_id }));
+}
+
+/// `count` votes of an archived proposal were removed.
+pub fn votes_archived(proposal_id: u32, count: u32) {
+    emit(
+        "votes_archived",
+        json!({ "proposal_id": proposal_id, "count": count }),
+    );
+}
+
+/// Member was added (`power` > 0), removed (`power` = 0) or its power changed.
+pub fn member_changed(member: &AccountId, power: u16) {
+    emit(
+        "member_changed",
+        json!({ "member": member, "power": power }),
+    );
+}
+
+/// A contract setting changed to `value`.
+pub fn settings_changed(setting: &str, value: Value) {
+    emit(
+        "settings_changed",
+        json!({ "setting": setting, "value": value }),
+    );
+}
+
+pub fn code_stored(code_hash: &[u8], size: usize) {
+    emit(
+        "code_stored",
+        json!({ "code_hash": Base64VecU8::from(code_hash.to_vec()), "size": size }),
+    );
+}
+
+/// The uploader removed a stored code.
+pub fn code_removed(code_hash: &[u8]) {
+    emit(
+        "code_removed",
+        json!({ "code_hash": Base64VecU8::from(code_hash.to_vec()) }),
+    );
+}
+
+pub fn state_migrated(from_version: &str) {
+    emit("state_migrated", json!({ "from_version": from_version }));
+}
+
+/// v0.2 proposals with id in `[from, to)` were migrated to the current layout.
+pub fn proposals_migrated(from: u32, to: u32) {
+    emit("proposals_migrated", json!({ "from": from, "to": to }));
+}
+
+/// Stake of the `account` changed to `amount`.
+pub fn stake_changed(account: &AccountId, amount: Balance) {
+    emit(
+        "stake_changed",
+        json!({ "account": account, "amount": U128(amount) }),
+    );
+}
+
+/// `voter` registered a vote key (`public_key`) or removed it (`None`).
+pub fn vote_key_changed(voter: &AccountId, public_key: Option<Base58PublicKey>) {
+    emit(
+        "vote_key_changed",
+        json!({ "voter": voter, "public_key": public_key }),
+    );
+}
+
+/// `member` confirmed an escrow milestone, which now has `support` confirmations power.
+pub fn milestone_confirmed(escrow_id: u32, milestone: u32, member: &AccountId, support: u32) {
+    emit(
+        "milestone_confirmed",
+        json!({
+            "escrow_id": escrow_id,
+            "milestone": milestone,
+            "member": member,
+            "support": support,
+        }),
+    );
+}
+
+/// A storage balance operation (`kind`): `storage_deposit`, `storage_withdraw` or
+/// `storage_unregister` of `amount`. `balance` is the account storage balance after it.
+pub fn storage(kind: &str, account: &AccountId, amount: Balance, balance: Balance) {
+    emit(
+        kind,
+        json!({ "account": account, "amount": U128(amount), "balance": U128(balance) }),
+    );
+}
+
+/// A treasury operation (`kind`) created by a proposal or by a member, eg
+/// `payment_claimed` or `escrow_created`. `id` is the payment, grant or escrow id.
+pub fn treasury(kind: &str, id: Option<u32>, account: &AccountId, amount: Balance) {
+    emit(
+        kind,
+        json!({ "id": id, "account": account, "amount": U128(amount) }),
+    );
+}
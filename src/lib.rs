@@ -2,10 +2,16 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashSet;
+use std::ops::Range;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::Vector;
-use near_sdk::json_types::{U128, U64};
-use near_sdk::{env, log, near_bindgen, AccountId, Balance, PanicOnDefault, Promise, StorageUsage};
+use near_sdk::collections::{LookupMap, UnorderedMap, Vector};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::{
+    env, near_bindgen, AccountId, Balance, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    PromiseResult, StorageUsage,
+};
 
 pub mod proposal;
 use crate::proposal::*;
@@ -13,16 +19,80 @@ use crate::proposal::*;
 pub mod config;
 use crate::config::*;
 
+pub mod treasury;
+use crate::treasury::*;
+
+pub mod payments;
+use crate::payments::*;
+
+pub mod vesting;
+use crate::vesting::*;
+
+pub mod allowances;
+use crate::allowances::*;
+
+pub mod escrow;
+use crate::escrow::*;
+
+pub mod migration;
+use crate::migration::*;
+
+pub mod upgrade;
+use crate::upgrade::*;
+
+pub mod archive;
+use crate::archive::*;
+
+pub mod storage;
+
+pub mod events;
+
+pub mod delegation;
+use crate::delegation::*;
+
+pub mod membership;
+use crate::membership::*;
+
+pub mod staking;
+use crate::staking::*;
+
+pub mod quadratic;
+use crate::quadratic::*;
+
+pub mod sponsorship;
+use crate::sponsorship::*;
+
+pub mod choice;
+use crate::choice::Ranking;
+
+pub mod ballot;
+use crate::ballot::*;
+
+pub mod relay;
+use crate::relay::*;
+
+pub mod conviction;
+use crate::conviction::*;
+
 // a way to optimize memory management
 near_sdk::setup_alloc!();
 
 const STORAGE_PRICE_PER_BYTE: Balance = env::STORAGE_PRICE_PER_BYTE;
 
+/// Gas attached to the `on_execute` callback.
+const ON_EXECUTE_GAS: Gas = 5_000_000_000_000;
+
+/// Contract state. Each change of the layout in a release requires a new `StateVersion` and
+/// a conversion in `Contract::migrate`. `StateVersion::V03` is the layout of the whole
+/// unreleased v0.3 series, changes made before the v0.3 release don't bump it.
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
     deployer_id: AccountId,
-    members: Vec<Voter>,
+    /// members voting power
+    members: UnorderedMap<AccountId, u16>,
+    /// sum of members power
+    total_power: u32,
     /// minimum support (in power) to pass the call
     min_support: u32,
     /// Each proposal voting duration must be between `min_duration` and `max_duration` expressed
@@ -33,6 +103,64 @@ pub struct Contract {
 
     next_idx: u32,
     proposals: Vector<Proposal>,
+    /// votes by (proposal_id, voter)
+    votes: LookupMap<(u32, AccountId), Vote>,
+    /// proposal voters by (proposal_id, n), n is lower than the proposal `voters`
+    vote_index: LookupMap<(u32, u32), VoterEntry>,
+    /// funds committed to passing proposals, by proposal id.
+    commitments: LookupMap<u32, Commitment>,
+    /// sum of the `commitments` amounts
+    committed: Balance,
+    /// funds reserved for obligations created by executed proposals (eg recurring payments).
+    reserved: Balance,
+    payments: Vector<RecurringPayment>,
+    grants: Vector<Grant>,
+    allowances: UnorderedMap<AccountId, Allowance>,
+    spends: Vector<Spend>,
+    escrows: Vector<Escrow>,
+    /// NEP-145 storage balances, used to pay for votes and proposals storage.
+    storage_deposits: LookupMap<AccountId, Balance>,
+    /// sum of all storage balances
+    storage_deposits_total: Balance,
+    vote_sponsorship: VoteSponsorship,
+    /// vote storage sponsored in the current period, by member
+    sponsored: LookupMap<AccountId, SponsoredUsage>,
+    /// current delegate by member
+    delegations: LookupMap<AccountId, AccountId>,
+    /// delegations history by delegate
+    delegators: LookupMap<AccountId, Vec<Delegator>>,
+    /// proposals with a lower id don't accept votes anymore, ended delegations which apply
+    /// only to them are pruned from the history.
+    voting_closed_before: u32,
+    /// policy snapshots by version
+    policies: Vector<Policy>,
+    /// member power changes: (policy version, power) list by member
+    member_history: LookupMap<AccountId, Vec<(u32, u16)>>,
+    /// NEP-141 token staked for the token voting
+    stake_token: Option<AccountId>,
+    stakes: LookupMap<AccountId, Stake>,
+    total_staked: Balance,
+    /// voice credits spent in the quadratic tally by voter
+    credits: LookupMap<AccountId, CreditsUsage>,
+    /// secret vote commits by (proposal_id, voter)
+    commits: LookupMap<(u32, AccountId), BallotCommit>,
+    /// keys signing off-chain votes by voter
+    vote_keys: LookupMap<AccountId, VoteKey>,
+    /// objection power rejecting optimistic proposals, zero disables optimistic proposals
+    objection_threshold: u32,
+    /// maximum amount transferred by an optimistic proposal, zero disables optimistic proposals
+    optimistic_max_amount: Balance,
+    /// conviction voting parameters, `None` disables conviction voting
+    conviction_params: Option<ConvictionParams>,
+    funding_requests: Vector<FundingRequest>,
+    /// power staked on funding requests by member, as (request_id, power) pairs
+    conviction_stakes: LookupMap<AccountId, Vec<(u32, u32)>>,
+    /// v0.2 proposals which are not migrated to the current layout yet.
+    proposals_migration: ProposalsMigration,
+    /// accounts which stored contract codes, by code hash
+    code_uploaders: LookupMap<Vec<u8>, AccountId>,
+    /// rankings of multi-option proposals by (proposal_id, voter)
+    ballots: LookupMap<(u32, AccountId), Ranking>,
 }
 
 #[near_bindgen]
@@ -40,13 +168,16 @@ impl Contract {
     /**
     Creates a new multisig NEAR wallet.
     Parameters:
-    + `members`: list of signers (voters) for this multisig wallet.
-    + `min_support`: minimum support a proposal have to get (in power votes) to pass.
+    + `members`: list of signers (voters) for this multisig wallet. Must not be empty nor
+       contain duplicates, each member power must be in [1...10000].
+    + `min_support`: minimum support a proposal have to get (in power votes) to pass. Must
+       not be bigger than the total members power.
     + `min_duration`: minimum voting time (in number of seconds) for a new proposal.
     + `max_duration`: maximum voting time (in number of seconds) for a new proposal.
     + `min_bond`: minimum deposit a caller have to put to create a new proposal. It includes
        the storage fees.
-    + NOTE: this parameters are binding for all proposals and can't be changed in the future. */
+    + NOTE: this parameters are binding for all proposals and can't be changed in the future.
+    The `validate_config` view runs the same checks on any configuration. */
     #[init]
     pub fn new(
         members: Vec<Voter>,
@@ -55,29 +186,61 @@ impl Contract {
         max_duration: u32,
         min_bond: U128,
     ) -> Self {
-        assert!(min_support > 0, "min_support must be positive");
-        for s in &members {
-            assert_valid_account(&s.account);
-        }
-        assert!(
-            min_duration >= 2 && max_duration > min_duration,
-            "min_duration and max_duration must be at least 2"
-        );
         let min_bond: u128 = min_bond.into();
-        assert!(
-            min_bond > STORAGE_PRICE_PER_BYTE,
-            "min_bond must be bigger than {}",
-            STORAGE_PRICE_PER_BYTE
-        );
+        let errors = config_errors(&members, min_support, min_duration, max_duration, min_bond);
+        assert!(errors.is_empty(), "{}", errors.join("; "));
+        write_state_version();
+        let (members, total_power) = members_map(&members);
+        let mut policies = Vector::new("y".into());
+        policies.push(&Policy {
+            min_support,
+            total_power,
+            token_power_unit: None,
+            tally: Tally::Linear,
+        });
         Self {
             deployer_id: env::predecessor_account_id(),
             members,
+            total_power,
             min_support,
             min_duration,
             max_duration,
             min_bond,
             next_idx: 0,
             proposals: Vector::new("p".into()),
+            votes: LookupMap::new("v".into()),
+            vote_index: LookupMap::new("x".into()),
+            commitments: LookupMap::new("j".into()),
+            committed: 0,
+            reserved: 0,
+            payments: Vector::new("r".into()),
+            grants: Vector::new("g".into()),
+            allowances: UnorderedMap::new("a".into()),
+            spends: Vector::new("s".into()),
+            escrows: Vector::new("e".into()),
+            storage_deposits: LookupMap::new("d".into()),
+            storage_deposits_total: 0,
+            vote_sponsorship: VoteSponsorship::default(),
+            sponsored: LookupMap::new("o".into()),
+            delegations: LookupMap::new("l".into()),
+            delegators: LookupMap::new("w".into()),
+            voting_closed_before: 0,
+            policies,
+            member_history: LookupMap::new("h".into()),
+            stake_token: None,
+            stakes: LookupMap::new("t".into()),
+            total_staked: 0,
+            credits: LookupMap::new("q".into()),
+            commits: LookupMap::new("b".into()),
+            vote_keys: LookupMap::new("k".into()),
+            objection_threshold: 0,
+            optimistic_max_amount: 0,
+            conviction_params: None,
+            funding_requests: Vector::new("f".into()),
+            conviction_stakes: LookupMap::new("i".into()),
+            proposals_migration: ProposalsMigration::default(),
+            code_uploaders: LookupMap::new("u".into()),
+            ballots: LookupMap::new("n".into()),
         }
     }
 
@@ -86,17 +249,27 @@ impl Contract {
     NewProposal is validated against the Contract parameters (min_duration, max_duration)
     and the caller have to provide a deposit = max(self.min_bond, this_tx_storage_cost).
     Once validate, the proposal is appended to the list of proposals and it's `index` is
-    returned. */
+    returned. Proposals which transfer more than the `available_balance` are rejected. */
     #[payable]
     pub fn add_proposal(&mut self, p: NewProposal) -> u32 {
         let storage_start = env::storage_usage();
-        self.proposals
-            .push(&p.into_proposal(self.min_duration, self.max_duration));
-        log!(
-            "New proposal added at timestamp={}seconds, id={}.",
-            env::block_timestamp(),
-            self.next_idx
+        assert!(
+            !p.optimistic || self.members.get(&env::predecessor_account_id()).is_some(),
+            "only members can create optimistic proposals"
         );
+        let proposal = p.into_proposal(
+            self.min_duration,
+            self.max_duration,
+            self.policy_version(),
+            self.objection_threshold,
+            self.optimistic_max_amount,
+        );
+        self.assert_valid_action(&proposal.action);
+        self.assert_available(proposal.action.amount(), None);
+        self.proposals.push(&proposal);
+        // optimistic proposals are passing since the creation.
+        self.update_commitment(self.next_idx, &proposal);
+        events::proposal_created(self.next_idx, &proposal.proposer);
         self.next_idx += 1;
         self.refund_storage(storage_start, true);
         return self.next_idx - 1;
@@ -105,101 +278,584 @@ impl Contract {
     /**
     Vote vote and signs a given proposal. `proposal_id` must be a valid and active proposal.
     Proposal is active if the current block is between proposal start and end block.
-    Only a valid signer (member of this multisig) can vote for a proposal. Each signer
-    can vote only once.
+    Only a valid signer (member of this multisig or a token holder with staked tokens in
+    the token voting) can vote for a proposal. Each signer can vote only once, but can
+    override a vote cast by its delegate. The vote casts also the power of members who
+    delegated to the signer and didn't vote yet.
+    Proposals with the quadratic tally require `vote_with_credits`, proposals with secret
+    ballots `commit_vote`.
     Parameters:
     + `proposal_id`: a valid proposal ID
     + `support`: true if you support the proposal, false otherwise.
      */
     #[payable]
     pub fn vote(&mut self, proposal_id: u32, support: bool) {
-        let a = env::predecessor_account_id();
-        let mut voter_o: Option<&Voter> = None;
-        for s in &self.members {
-            if s.account == a {
-                voter_o = Some(s);
-                break;
-            }
-        }
-        let voter = voter_o.expect(&format!("you ({}) are not authorized to vote", a));
-        let idx: u64 = proposal_id.into();
-        let p = &mut self.proposals.get(idx).expect("proposal_id not found");
-        let storage_start = env::storage_usage();
-        p.vote(voter, support);
-        self.proposals.replace(idx, p);
-        self.refund_storage(storage_start, false);
+        self.cast_vote(proposal_id, support, None);
+    }
+
+    /// Votes on a proposal with the quadratic tally, spending `credits` voice credits of
+    /// the current epoch. The vote counts with the square root of `credits`.
+    /// Delegations don't apply in the quadratic tally.
+    #[payable]
+    pub fn vote_with_credits(&mut self, proposal_id: u32, support: bool, credits: u32) {
+        self.cast_vote(proposal_id, support, Some(credits));
     }
 
     /**
     Execute executes given proposal. A proposal can be executed only once and only after the
     voting period passed and before the `proposal.execute_before`.
+    Funds are committed to passing proposals in the order they reached the required support,
+    as long as the available balance covers them.
+    A proposal can't be executed if the account balance doesn't cover it together with
+    the storage cost and funds committed to other proposals.
+    When the action creates a promise, `on_execute` reports its failure with the
+    `execution_failed` event.
     Anyone can call this functions. */
-    pub fn execute(&mut self, proposal_id: u32) -> Promise {
+    pub fn execute(&mut self, proposal_id: u32) -> PromiseOrValue<()> {
         let idx: u64 = proposal_id.into();
-        let p = &mut self.proposals.get(idx).expect("proposal_id not found");
-        let promise = p.execute(self.min_support);
+        let p = &mut self.get_proposal(proposal_id);
+        let min_support = self.policy(p.policy_version).min_votes();
+        let ballots = self.choice_ballots(proposal_id, p);
+        p.execute(min_support, &ballots);
+        let action = p.winning_action(min_support, &ballots).unwrap();
+        self.assert_available(action.amount(), Some(proposal_id));
         self.proposals.replace(idx, p);
-        log!("Proposal {} executed.", proposal_id);
-        return promise;
+        self.update_commitment(proposal_id, p);
+        let result = match self.execute_action(action) {
+            // deleted account can't handle the callback.
+            PromiseOrValue::Promise(promise) if !matches!(action, ActionInt::Delete { .. }) => {
+                promise
+                    .then(
+                        Promise::new(env::current_account_id()).function_call(
+                            b"on_execute".to_vec(),
+                            serde_json::json!({ "proposal_id": proposal_id })
+                                .to_string()
+                                .into_bytes(),
+                            0,
+                            ON_EXECUTE_GAS,
+                        ),
+                    )
+                    .into()
+            }
+            r => r,
+        };
+        events::proposal_executed(proposal_id);
+        return result;
+    }
+
+    /// Callback of the proposal action promise. Emits `execution_failed` if the action failed.
+    /// After a successful `Upgrade` the deployed code is removed from the storage.
+    #[private]
+    pub fn on_execute(&mut self, proposal_id: u32) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            events::execution_failed(proposal_id);
+            return;
+        }
+        let p = self.get_proposal(proposal_id);
+        let min_support = self.policy(p.policy_version).min_votes();
+        // the executed option of a multi-option proposal is recorded, ballots are not needed.
+        if let Some(ActionInt::Upgrade { code_hash }) = p.winning_action(min_support, &[]) {
+            self.on_upgraded(code_hash);
+        }
     }
 
     /// Returns proposal by id.
     /// Panics when `proposal_id` is not found.
     pub fn proposal(&self, proposal_id: u32) -> ProposalOut {
-        assert!(proposal_id < self.next_idx, "proposal_id not found");
-        let idx: u64 = proposal_id.into();
-        let p = self.proposals.get(idx).expect("proposal_id not found");
-        p.into()
+        self.get_proposal(proposal_id).into()
+    }
+
+    /// Returns the vote of `voter` for the given proposal, if it exists.
+    pub fn proposal_vote(&self, proposal_id: u32, voter: ValidAccountId) -> Option<Vote> {
+        self.votes.get(&(proposal_id, voter.into()))
     }
 
     /// Returns Conract configuration.
     pub fn settings(&self) -> Settings {
         Settings {
             deployer_id: self.deployer_id.clone(),
-            members: serde_json::to_string(&self.members).unwrap(),
+            members: serde_json::to_string(&self.members_list()).unwrap(),
+            total_power: self.total_power,
             min_support: self.min_support,
             min_duration: self.min_duration,
             max_duration: self.max_duration,
             min_bond: self.min_bond.into(),
+            vote_sponsorship_cap: self.vote_sponsorship.cap.into(),
+            vote_sponsorship_period: self.vote_sponsorship.period,
+            objection_threshold: self.objection_threshold,
+            optimistic_max_amount: self.optimistic_max_amount.into(),
+            conviction: self.conviction_params.clone(),
             unix_time: U64::from(env::block_timestamp() / FROM_NANO),
         }
     }
 
-    fn refund_storage(&self, initial_storage: StorageUsage, check_bond: bool) {
+    fn members_list(&self) -> Vec<Voter> {
+        self.members
+            .iter()
+            .map(|(account, power)| Voter { account, power })
+            .collect()
+    }
+
+    /// Returns the amount of NEAR (in yocto) which is free to allocate: the account balance
+    /// minus the storage cost, funds committed to passing proposals and funds reserved for
+    /// recurring payments, grants, allowances and escrows.
+    pub fn available_balance(&self) -> U128 {
+        self.available(None).into()
+    }
+
+    /// Validates the action against the contract state.
+    fn assert_valid_action(&self, a: &ActionInt) {
+        match a {
+            ActionInt::CreateEscrow {
+                release_support, ..
+            } => assert!(
+                *release_support <= self.min_support,
+                "release_support must not be bigger than min_support"
+            ),
+            ActionInt::Upgrade { code_hash } => assert!(
+                env::storage_has_key(&code_key(code_hash)),
+                "code not found, use store_code first"
+            ),
+            // the power of token voters is not known upfront.
+            ActionInt::SetTally {
+                tally:
+                    Tally::Quadratic {
+                        credits_per_power, ..
+                    },
+            } if self.stake_token.is_none() => {
+                let max_votes: u32 = self
+                    .members
+                    .values()
+                    .map(|power| quadratic_votes(power.into(), *credits_per_power))
+                    .sum();
+                let min_votes = quadratic_votes(self.min_support, *credits_per_power);
+                assert!(
+                    min_votes <= max_votes,
+                    "members can't reach min_support in the quadratic tally (votes: {}, required: {})",
+                    max_votes,
+                    min_votes
+                );
+            }
+            ActionInt::CreateSubAccount {
+                name, code_hash, ..
+            } => {
+                assert!(
+                    !name.is_empty() && !name.contains('.'),
+                    "sub-account name {:?} is not valid",
+                    name
+                );
+                assert_valid_account(&sub_account_id(name));
+                if let Some(h) = code_hash {
+                    assert!(
+                        env::storage_has_key(&code_key(h)),
+                        "code not found, use store_code first"
+                    );
+                }
+            }
+            ActionInt::SetObjectionThreshold { threshold, .. } => assert!(
+                *threshold >= 1 && *threshold <= self.total_power,
+                "objection threshold must be between 1 and the total members power ({})",
+                self.total_power
+            ),
+            ActionInt::Choice { options, .. } => {
+                for o in options {
+                    self.assert_valid_action(o);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn execute_action(&mut self, a: &ActionInt) -> PromiseOrValue<()> {
+        match a {
+            ActionInt::Transfer { dest, amount } => {
+                Promise::new(dest.clone()).transfer(*amount).into()
+            }
+            ActionInt::Delete { dest } => {
+                // staked tokens would be lost and storage balances sent to `dest`.
+                assert!(
+                    self.total_staked == 0,
+                    "can't delete the account while tokens are staked"
+                );
+                assert!(
+                    self.storage_deposits_total == 0,
+                    "can't delete the account while storage balances exist"
+                );
+                Promise::new(env::current_account_id())
+                    .delete_account(dest.clone())
+                    .into()
+            }
+            ActionInt::RecurringPayment {
+                recipient,
+                amount,
+                start,
+                interval,
+                count,
+                end,
+            } => {
+                self.create_payment(RecurringPayment {
+                    recipient: recipient.clone(),
+                    amount: *amount,
+                    start: *start,
+                    interval: *interval,
+                    installments: installments(*start, *interval, *count, *end),
+                    claimed: 0,
+                });
+                PromiseOrValue::Value(())
+            }
+            ActionInt::CancelPayment { payment_id } => {
+                self.cancel_payment(*payment_id);
+                PromiseOrValue::Value(())
+            }
+            ActionInt::CreateGrant {
+                beneficiary,
+                total,
+                start,
+                cliff,
+                duration,
+                revocable,
+            } => {
+                self.create_grant(Grant {
+                    beneficiary: beneficiary.clone(),
+                    total: *total,
+                    start: *start,
+                    cliff: *cliff,
+                    duration: *duration,
+                    revocable: *revocable,
+                    revoked_at: None,
+                    withdrawn: 0,
+                });
+                PromiseOrValue::Value(())
+            }
+            ActionInt::RevokeGrant { grant_id } => {
+                self.revoke_grant(*grant_id);
+                PromiseOrValue::Value(())
+            }
+            ActionInt::GrantAllowance {
+                member,
+                amount,
+                expiry,
+            } => {
+                self.grant_allowance(
+                    member,
+                    Allowance {
+                        amount: *amount,
+                        expiry: *expiry,
+                    },
+                );
+                PromiseOrValue::Value(())
+            }
+            ActionInt::CreateEscrow {
+                beneficiary,
+                milestones,
+                release_support,
+            } => {
+                self.create_escrow(Escrow {
+                    beneficiary: beneficiary.clone(),
+                    milestones: milestones
+                        .iter()
+                        .map(|(name, amount)| Milestone {
+                            name: name.clone(),
+                            amount: *amount,
+                            confirmations: HashSet::new(),
+                            support: 0,
+                            released: false,
+                        })
+                        .collect(),
+                    release_support: *release_support,
+                    policy_version: self.policy_version(),
+                    cancelled: false,
+                });
+                PromiseOrValue::Value(())
+            }
+            ActionInt::CancelEscrow { escrow_id } => {
+                self.cancel_escrow(*escrow_id);
+                PromiseOrValue::Value(())
+            }
+            ActionInt::Upgrade { code_hash } => self.upgrade(code_hash).into(),
+            ActionInt::AddFullAccessKey { public_key } => Promise::new(env::current_account_id())
+                .add_full_access_key(public_key.clone())
+                .into(),
+            ActionInt::AddFunctionCallKey {
+                public_key,
+                allowance,
+                receiver_id,
+                method_names,
+            } => Promise::new(env::current_account_id())
+                .add_access_key(
+                    public_key.clone(),
+                    *allowance,
+                    receiver_id.clone(),
+                    method_names.join(",").into_bytes(),
+                )
+                .into(),
+            ActionInt::DeleteKey { public_key } => Promise::new(env::current_account_id())
+                .delete_key(public_key.clone())
+                .into(),
+            ActionInt::CreateSubAccount {
+                name,
+                amount,
+                public_key,
+                code_hash,
+            } => {
+                let mut p = Promise::new(sub_account_id(name))
+                    .create_account()
+                    .transfer(*amount);
+                if let Some(k) = public_key {
+                    p = p.add_full_access_key(k.clone());
+                }
+                if let Some(h) = code_hash {
+                    p = p.deploy_contract(read_code(h));
+                }
+                p.into()
+            }
+            ActionInt::SetVoteSponsorship { cap, period } => {
+                self.vote_sponsorship = VoteSponsorship {
+                    cap: *cap,
+                    period: *period,
+                };
+                events::settings_changed(
+                    "vote_sponsorship",
+                    serde_json::json!({ "cap": U128(*cap), "period": period }),
+                );
+                PromiseOrValue::Value(())
+            }
+            ActionInt::SetObjectionThreshold {
+                threshold,
+                max_amount,
+            } => {
+                self.objection_threshold = *threshold;
+                self.optimistic_max_amount = *max_amount;
+                events::settings_changed(
+                    "objection_threshold",
+                    serde_json::json!({ "threshold": threshold, "max_amount": U128(*max_amount) }),
+                );
+                PromiseOrValue::Value(())
+            }
+            ActionInt::SetConviction { params } => {
+                self.conviction_params = params.clone();
+                events::settings_changed("conviction", serde_json::json!(params));
+                PromiseOrValue::Value(())
+            }
+            ActionInt::SetMember { member, power } => {
+                self.set_member(member, *power);
+                PromiseOrValue::Value(())
+            }
+            ActionInt::SetTokenVoting { token, power_unit } => {
+                self.set_token_voting(token, *power_unit);
+                PromiseOrValue::Value(())
+            }
+            ActionInt::SetTally { tally } => {
+                self.new_policy_version(|p| p.tally = tally.clone());
+                events::settings_changed("tally", serde_json::json!(tally));
+                PromiseOrValue::Value(())
+            }
+            // only the winning option is executed.
+            ActionInt::Choice { .. } => unreachable!(),
+        }
+    }
+
+    /// Records the caller vote and charges the caller for the vote storage. `credits` are
+    /// set by `vote_with_credits` and required by the quadratic tally.
+    fn cast_vote(&mut self, proposal_id: u32, support: bool, credits: Option<u32>) {
+        let a = env::predecessor_account_id();
+        let storage_start = env::storage_usage();
+        let indexed = self.record_vote(&a, proposal_id, support, credits);
+        if !self.sponsor_vote_storage(&a, proposal_id, indexed, storage_start) {
+            self.refund_storage(storage_start, false);
+        }
+    }
+
+    /// Records the `a` vote, together with votes of members who delegated to `a`.
+    /// Returns the range of the voters index entries added for the proposal.
+    fn record_vote(
+        &mut self,
+        a: &AccountId,
+        proposal_id: u32,
+        support: bool,
+        credits: Option<u32>,
+    ) -> Range<u32> {
+        let idx: u64 = proposal_id.into();
+        let p = &mut self.get_proposal(proposal_id);
+        let first = p.voters;
+        assert!(
+            !matches!(p.action, ActionInt::Choice { .. }),
+            "use vote_option for multi-option proposals"
+        );
+        assert!(
+            p.reveal_end.is_none(),
+            "proposal uses secret ballots, use commit_vote"
+        );
+        let power = self.take_voting_power(a, p);
+        assert!(power > 0, "you ({}) are not authorized to vote", a);
+        let tally = self.policy(p.policy_version).tally;
+        let power = match tally {
+            Tally::Linear => {
+                assert!(
+                    credits.is_none(),
+                    "proposal uses the linear tally, use vote"
+                );
+                power
+            }
+            Tally::Quadratic {
+                credits_per_power,
+                epoch,
+            } => {
+                let credits =
+                    credits.expect("proposal uses the quadratic tally, use vote_with_credits");
+                let budget = u64::from(power) * u64::from(credits_per_power);
+                self.spend_credits(a, budget, epoch, credits)
+            }
+        };
+        let key = (proposal_id, a.clone());
+        match self.votes.get(&key) {
+            Some(prev) => {
+                assert!(prev.delegated, "you already voted");
+                p.remove_vote(&prev);
+            }
+            None => self.index_voter(proposal_id, p, a),
+        }
+        let vote = p.vote(power, support);
+        self.votes.insert(&key, &vote);
+        if matches!(tally, Tally::Linear) {
+            self.vote_delegated(proposal_id, p, a, support);
+        }
+        self.proposals.replace(idx, p);
+        self.update_commitment(proposal_id, p);
+        events::vote_cast(proposal_id, a, support, vote.power);
+        first..p.voters
+    }
+
+    /// Returns the account balance minus the storage cost, storage balances, reserved funds
+    /// and commitments. When `owner` is set, funds committed to the `owner` proposal are
+    /// counted as available.
+    fn available(&self, owner: Option<u32>) -> Balance {
+        let own = owner
+            .and_then(|id| self.commitments.get(&id))
+            .map_or(0, |c| c.amount);
+        let locked = Balance::from(env::storage_usage()) * STORAGE_PRICE_PER_BYTE
+            + self.storage_deposits_total
+            + self.reserved
+            + (self.committed - own);
+        env::account_balance().saturating_sub(locked)
+    }
+
+    fn assert_available(&self, amount: Balance, owner: Option<u32>) {
+        if amount == 0 {
+            return;
+        }
+        let available = self.available(owner);
+        assert!(
+            amount <= available,
+            "not enough available balance (available: {}, required: {})",
+            available,
+            amount
+        );
+    }
+
+    /// Commits funds for the proposal if it's passing and the available balance covers it,
+    /// otherwise releases its commitment. Funds committed to a proposal can't be used by
+    /// other proposals, so proposals are funded in the order they got committed.
+    /// A multi-option proposal commits the amount of the currently winning option.
+    fn update_commitment(&mut self, proposal_id: u32, p: &Proposal) {
+        let min_support = self.policy(p.policy_version).min_votes();
+        let ballots = self.choice_ballots(proposal_id, p);
+        let amount = p
+            .winning_action(min_support, &ballots)
+            .map_or(0, ActionInt::amount);
+        let passing = amount > 0 && p.is_passing(min_support, &ballots);
+        // the proposal own commitment counts, since the winning option may change.
+        let fits = passing && amount <= self.available(Some(proposal_id));
+        self.release_commitment(proposal_id);
+        if fits {
+            self.commitments.insert(
+                &proposal_id,
+                &Commitment {
+                    amount,
+                    execute_before: p.execute_before,
+                },
+            );
+            self.committed += amount;
+        }
+    }
+
+    /// Releases funds committed to the proposal, if any.
+    fn release_commitment(&mut self, proposal_id: u32) {
+        if let Some(c) = self.commitments.remove(&proposal_id) {
+            self.committed -= c.amount;
+        }
+    }
+
+    /// Charges the caller for the storage used since `initial_storage` (at least `min_bond`
+    /// when `check_bond` is set) or refunds the freed storage. When the attached deposit is
+    /// not enough, the missing amount is drawn from the caller storage balance.
+    fn refund_storage(&mut self, initial_storage: StorageUsage, check_bond: bool) {
         let current_storage = env::storage_usage();
         let attached_deposit = env::attached_deposit();
+        let account = env::predecessor_account_id();
         let refund_amount = if current_storage > initial_storage {
             let mut required_deposit =
                 Balance::from(current_storage - initial_storage) * STORAGE_PRICE_PER_BYTE;
             if check_bond && required_deposit < self.min_bond {
                 required_deposit = self.min_bond
             }
-            assert!(
-                required_deposit <= attached_deposit,
-                "The required attached deposit is {}, but the given attached deposit is is {}",
-                required_deposit,
-                attached_deposit,
-            );
-            attached_deposit - required_deposit
+            if required_deposit > attached_deposit && self.storage_deposits.contains_key(&account) {
+                self.storage_charge(&account, required_deposit - attached_deposit);
+                0
+            } else {
+                assert!(
+                    required_deposit <= attached_deposit,
+                    "The required attached deposit is {}, but the given attached deposit is is {}",
+                    required_deposit,
+                    attached_deposit,
+                );
+                attached_deposit - required_deposit
+            }
         } else {
-            attached_deposit
-                + Balance::from(initial_storage - current_storage) * STORAGE_PRICE_PER_BYTE
+            let freed = Balance::from(initial_storage - current_storage) * STORAGE_PRICE_PER_BYTE;
+            if self.storage_deposits.contains_key(&account) {
+                self.storage_refund(&account, freed);
+                attached_deposit
+            } else {
+                attached_deposit + freed
+            }
         };
         if refund_amount > 0 {
-            Promise::new(env::predecessor_account_id()).transfer(refund_amount);
+            Promise::new(account).transfer(refund_amount);
         }
     }
 }
 
+/// Creates members map from the members list. Returns the map and the sum of members power.
+fn members_map(members: &[Voter]) -> (UnorderedMap<AccountId, u16>, u32) {
+    let mut m = UnorderedMap::new("m".into());
+    for v in members {
+        assert_valid_account(&v.account);
+        m.insert(&v.account, &v.power);
+    }
+    let total_power = m.values().map(u32::from).sum();
+    (m, total_power)
+}
+
+/// Returns `<name>.<current account>` account id.
+fn sub_account_id(name: &str) -> AccountId {
+    format!("{}.{}", name, env::current_account_id())
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryInto;
 
     use super::*;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+    use near_sdk::json_types::Base58PublicKey;
+    use near_sdk::test_utils::{
+        accounts, get_logs, testing_env_with_promise_results, VMContextBuilder,
+    };
     use near_sdk::{testing_env, MockedBlockchain};
 
     mod tutils;
+    use crate::choice::{winner, ChoiceMethod};
     use crate::tests::tutils::deserialize_receipts;
 
     const BASE_UNIT: Balance = STORAGE_PRICE_PER_BYTE * 20;
@@ -282,12 +938,18 @@ mod tests {
     #[test]
     fn test_constructor_should_work() {
         init_blockchain();
-        Contract::new(Vec::new(), 10, 2, 20, BASE_UNIT.into());
-        Contract::new(Vec::new(), 1000, 2, 2000, BASE_UNIT.into());
+        let members = || {
+            vec![Voter {
+                account: accounts(0).into(),
+                power: 1000,
+            }]
+        };
+        Contract::new(members(), 10, 2, 20, BASE_UNIT.into());
+        Contract::new(members(), 1000, 2, 2000, BASE_UNIT.into());
         let ctr = Contract::new(
             vec![Voter {
                 account: accounts(0).into(),
-                power: 3,
+                power: 10,
             }],
             10,
             20,
@@ -296,13 +958,65 @@ mod tests {
         );
         let c = ctr.settings();
         assert_eq!(c.deployer_id, "bob.near");
-        assert_eq!(c.members, "[{\"account\":\"alice\",\"power\":3}]");
+        assert_eq!(c.members, "[{\"account\":\"alice\",\"power\":10}]");
+        assert_eq!(c.total_power, 10);
         assert_eq!(c.min_support, 10);
         assert_eq!(c.min_duration, 20);
         assert_eq!(c.max_duration, 21);
         assert_eq!(c.min_bond, U128::from(2 * BASE_UNIT));
     }
 
+    fn voter(account: usize, power: u16) -> Voter {
+        Voter {
+            account: accounts(account).into(),
+            power,
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "members list must not be empty")]
+    fn test_constructor_no_members() {
+        init_blockchain();
+        Contract::new(Vec::new(), 1, 2, 20, BASE_UNIT.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "member alice is duplicated")]
+    fn test_constructor_duplicated_member() {
+        init_blockchain();
+        Contract::new(vec![voter(0, 1), voter(0, 1)], 1, 2, 20, BASE_UNIT.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "member bob power must be between 1 and 10000")]
+    fn test_constructor_zero_power() {
+        init_blockchain();
+        Contract::new(vec![voter(0, 1), voter(1, 0)], 1, 2, 20, BASE_UNIT.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "min_support (5) is bigger than the total members power (3)")]
+    fn test_constructor_min_support_too_big() {
+        init_blockchain();
+        Contract::new(vec![voter(0, 1), voter(1, 2)], 5, 2, 20, BASE_UNIT.into());
+    }
+
+    #[test]
+    fn test_validate_config() {
+        init_blockchain();
+        let members = vec![voter(0, 1), voter(1, 2)];
+        assert!(Contract::validate_config(members, 3, 2, 20, BASE_UNIT.into()).is_empty());
+        let members = vec![voter(0, 10001), voter(0, 2)];
+        assert_eq!(
+            Contract::validate_config(members, 20000, 2, 20, BASE_UNIT.into()),
+            vec![
+                "member alice power must be between 1 and 10000".to_string(),
+                "member alice is duplicated".to_string(),
+                "min_support (20000) is bigger than the total members power (10003)".to_string(),
+            ]
+        );
+    }
+
     fn setup_with_proposal() -> (VMContextBuilder, Contract, NewProposal) {
         let (mut ctx, mut contract) = setup_contract(5);
         // alice creates a proposal
@@ -316,13 +1030,13 @@ mod tests {
     fn test_happy_path() {
         let (mut ctx, mut contract, _p) = setup_with_proposal();
         // alice votes
-        update_context(&mut ctx, 0, BASE_UNIT, 10);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
         contract.vote(0, true);
         // bob votes
-        update_context(&mut ctx, 1, BASE_UNIT, 11);
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 11);
         contract.vote(0, false);
         // charlie votes
-        update_context(&mut ctx, 2, BASE_UNIT, 12);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 12);
         contract.vote(0, true);
 
         update_context(&mut ctx, 2, BASE_UNIT, 21);
@@ -341,13 +1055,142 @@ mod tests {
 
         let receipts = deserialize_receipts();
         println!("Receipts: {:?}", receipts[0]);
-        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts.len(), 2);
         assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
         assert_eq!(receipts[0].actions.len(), 1);
         match &receipts[0].actions[0] {
             tutils::Action::Transfer(t) => assert_eq!(t.deposit, DEFAULT_TRANSFER),
             _ => panic!("invalid action type"),
         }
+        assert_eq!(receipts[1].receiver_id, env::current_account_id());
+        match &receipts[1].actions[0] {
+            tutils::Action::FunctionCall(f) => assert_eq!(f.method_name, b"on_execute"),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"vostok-dao","version":"1.0.0","event":"proposal_created","data":[{"proposal_id":0,"proposer":"alice"}]}"#
+            ]
+        );
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote(0, false);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"vostok-dao","version":"1.0.0","event":"vote_cast","data":[{"power":3,"proposal_id":0,"support":false,"voter":"bob"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vote_recorded() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote(0, false);
+        assert_eq!(
+            contract.proposal_vote(0, accounts(1)),
+            Some(Vote {
+                support: Some(false),
+                power: 3,
+                delegated: false,
+            })
+        );
+        assert_eq!(contract.proposal_vote(0, accounts(0)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "you already voted")]
+    fn test_vote_twice() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote(0, false);
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 11);
+        contract.vote(0, true);
+    }
+
+    #[test]
+    fn test_archive_proposal() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.description = "transfer to danny ".repeat(10);
+        contract.add_proposal(p);
+        vote_alice_and_charile(&mut ctx, &mut contract);
+        update_context(&mut ctx, 4, 0, 40);
+        contract.execute(0);
+
+        // charlie's vote is removed first, alice gets refunds for the description and for
+        // her vote.
+        update_context(&mut ctx, 4, 0, 41);
+        assert_eq!(contract.archive_proposal(0, 1), 1);
+        assert_eq!(
+            receivers(),
+            vec![AccountId::from(accounts(0)), AccountId::from(accounts(2))]
+        );
+        assert_eq!(contract.proposal_vote(0, accounts(2)), None);
+        assert!(contract.proposal_vote(0, accounts(0)).is_some());
+
+        update_context(&mut ctx, 4, 0, 42);
+        assert_eq!(contract.archive_proposal(0, 1), 0);
+        assert_eq!(receivers(), vec![AccountId::from(accounts(0))]);
+        let p = contract.proposal(0);
+        assert_eq!(p.description, "");
+        assert_eq!(
+            p.description_hash,
+            Some(env::sha256("transfer to danny ".repeat(10).as_bytes()).into())
+        );
+        assert_eq!(p.executed, true);
+        assert_eq!(p.votes_for, 6);
+        assert_eq!(contract.proposal_vote(0, accounts(0)), None);
+    }
+
+    /// Returns receivers of the receipts created in the current context.
+    fn receivers() -> Vec<AccountId> {
+        deserialize_receipts()
+            .into_iter()
+            .map(|r| r.receiver_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_archive_expired_proposal() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+        assert_eq!(contract.committed, DEFAULT_TRANSFER);
+        update_context(&mut ctx, 4, 0, 101);
+        contract.archive_proposal(0, 10);
+        assert_eq!(contract.proposal(0).executed, false);
+        assert_eq!(contract.proposal_vote(0, accounts(1)), None);
+        // the expired proposal doesn't hold funds any more.
+        assert!(contract.commitments.get(&0).is_none());
+        assert_eq!(contract.committed, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal is not finalized")]
+    fn test_archive_active_proposal() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        vote_alice_and_charile(&mut ctx, &mut contract);
+        update_context(&mut ctx, 4, 0, 40);
+        contract.archive_proposal(0, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal already archived")]
+    fn test_archive_twice() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 4, 0, 101);
+        contract.archive_proposal(0, 10);
+        contract.archive_proposal(0, 10);
     }
 
     #[test]
@@ -372,7 +1215,12 @@ mod tests {
                 votes_for: 0,
                 votes_against: 0,
                 execute_before: p.execute_before,
-                executed: false
+                executed: false,
+                description_hash: None,
+                policy_version: 0,
+                reveal_end: None,
+                objection_threshold: None,
+                executed_option: None,
             }
         );
 
@@ -385,7 +1233,7 @@ mod tests {
     fn test_vote_too_early() {
         let (mut ctx, mut contract, _p_in) = setup_with_proposal();
         // alice votes too early
-        update_context(&mut ctx, 0, BASE_UNIT, 5);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 5);
         contract.vote(0, true);
     }
 
@@ -394,7 +1242,7 @@ mod tests {
     fn test_vote_too_late() {
         let (mut ctx, mut contract, _p_in) = setup_with_proposal();
         // alice votes too late
-        update_context(&mut ctx, 0, BASE_UNIT, 31);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 31);
         contract.vote(0, true);
     }
 
@@ -403,7 +1251,7 @@ mod tests {
     fn test_vote_too_late2() {
         let (mut ctx, mut contract, _p_in) = setup_with_proposal();
         // alice votes too late - after execution period
-        update_context(&mut ctx, 0, BASE_UNIT, 101);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 101);
         contract.vote(0, true);
     }
 
@@ -412,13 +1260,13 @@ mod tests {
     fn test_vote_not_authorized() {
         let (mut ctx, mut contract, _p_in) = setup_with_proposal();
         // danny is not authorized to vote
-        update_context(&mut ctx, 3, BASE_UNIT, 12);
+        update_context(&mut ctx, 3, BASE_UNIT * 4, 12);
         contract.vote(0, true);
     }
 
     #[test]
     #[should_panic(
-        expected = "The required attached deposit is 90000000000000000000, but the given attached deposit is is 10000"
+        expected = "The required attached deposit is 1290000000000000000000, but the given attached deposit is is 10000"
     )]
     fn test_vote_not_enough_deposit() {
         let (mut ctx, mut contract, _p_in) = setup_with_proposal();
@@ -431,7 +1279,7 @@ mod tests {
     #[should_panic(expected = "proposal didn't get enough support (got 2, required: 5)")]
     fn test_execute_not_enough_support() {
         let (mut ctx, mut contract, _p) = setup_with_proposal();
-        update_context(&mut ctx, 0, BASE_UNIT, 10);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
         contract.vote(0, true);
 
         update_context(&mut ctx, 4, 0, 31);
@@ -487,9 +1335,9 @@ mod tests {
     #[test]
     fn test_execute_with_exact_support() {
         let (mut ctx, mut contract, _p) = setup_with_proposal();
-        update_context(&mut ctx, 0, BASE_UNIT, 10);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
         contract.vote(0, true);
-        update_context(&mut ctx, 1, BASE_UNIT, 10); // together, alice and bob have power=5
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10); // together, alice and bob have power=5
         contract.vote(0, true);
         update_context(&mut ctx, 4, 0, 40);
         contract.execute(0);
@@ -510,23 +1358,2188 @@ mod tests {
         assert!(out.contains("\"dest\":\"danny\""), out);
     }
 
-    fn vote_alice_and_charile(ctx: &mut VMContextBuilder, contract: &mut Contract) {
-        update_context(ctx, 0, BASE_UNIT, 10);
-        contract.vote(0, true);
-        update_context(ctx, 2, BASE_UNIT, 10); // charile power = 5
-        contract.vote(0, true);
+    #[test]
+    fn test_available_balance() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        let storage = Balance::from(env::storage_usage()) * STORAGE_PRICE_PER_BYTE;
+        assert_eq!(
+            contract.available_balance(),
+            U128::from(env::account_balance() - storage)
+        );
+
+        vote_alice_and_charile(&mut ctx, &mut contract);
+        let storage = Balance::from(env::storage_usage()) * STORAGE_PRICE_PER_BYTE;
+        assert_eq!(
+            contract.available_balance(),
+            U128::from(env::account_balance() - storage - DEFAULT_TRANSFER)
+        );
+
+        update_context(&mut ctx, 4, 0, 40);
+        contract.execute(0);
+        assert!(contract.commitments.get(&0).is_none());
+        assert_eq!(contract.committed, 0);
     }
 
-    fn sample_proposal() -> NewProposal {
-        NewProposal {
-            action: Action::Transfer {
-                dest: accounts(3),
+    #[test]
+    #[should_panic(expected = "not enough available balance")]
+    fn test_add_proposal_overdraw() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(transfer_proposal(1000 * ONE_NEAR));
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough available balance")]
+    fn test_execute_overdraw() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        // each proposal can be covered, but not both of them
+        contract.add_proposal(transfer_proposal(60 * ONE_NEAR));
+        contract.add_proposal(transfer_proposal(60 * ONE_NEAR));
+        for id in 0..2 {
+            update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+            contract.vote(id, true);
+            update_context(&mut ctx, 2, BASE_UNIT * 4, 10);
+            contract.vote(id, true);
+        }
+        assert!(contract.commitments.get(&0).is_some());
+        assert!(contract.commitments.get(&1).is_none());
+
+        // proposal 0 was committed first, so proposal 1 can't be executed before it.
+        update_context(&mut ctx, 4, 0, 40);
+        contract.execute(1);
+    }
+
+    #[test]
+    fn test_recurring_payment() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::RecurringPayment {
+                recipient: accounts(3),
                 amount: DEFAULT_TRANSFER.into(),
+                start: 50.into(),
+                interval: 10,
+                count: Some(3),
+                end: None,
             },
-            description: "transfer to danny".into(),
-            voting_start: 10.into(),
-            voting_duration: 20,
-            execute_before: 100.into(),
+        );
+        assert_eq!(contract.reserved, 3 * DEFAULT_TRANSFER);
+        assert_eq!(contract.payment(0).installments, 3);
+
+        // two installments are due at 61
+        update_context(&mut ctx, 3, 0, 61);
+        contract.claim_payment(0);
+        assert_eq!(contract.payment(0).claimed, 2);
+        assert_eq!(contract.reserved, DEFAULT_TRANSFER);
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, 2 * DEFAULT_TRANSFER),
+            _ => panic!("invalid action type"),
         }
+
+        update_context(&mut ctx, 3, 0, 1000);
+        contract.claim_payment(0);
+        assert_eq!(contract.payment(0).claimed, 3);
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "no installment is due")]
+    fn test_claim_payment_not_due() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::RecurringPayment {
+                recipient: accounts(3),
+                amount: DEFAULT_TRANSFER.into(),
+                start: 50.into(),
+                interval: 10,
+                count: None,
+                end: Some(75.into()),
+            },
+        );
+        assert_eq!(contract.payment(0).installments, 3);
+        update_context(&mut ctx, 3, 0, 49);
+        contract.claim_payment(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "only the recipient can claim the payment")]
+    fn test_claim_payment_not_recipient() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::RecurringPayment {
+                recipient: accounts(3),
+                amount: DEFAULT_TRANSFER.into(),
+                start: 50.into(),
+                interval: 10,
+                count: Some(2),
+                end: None,
+            },
+        );
+        update_context(&mut ctx, 0, 0, 60);
+        contract.claim_payment(0);
+    }
+
+    #[test]
+    fn test_cancel_payment() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::RecurringPayment {
+                recipient: accounts(3),
+                amount: DEFAULT_TRANSFER.into(),
+                start: 30.into(),
+                interval: 5,
+                count: Some(10),
+                end: None,
+            },
+        );
+        // the cancel proposal is executed at 40, when 3 installments are due.
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::CancelPayment { payment_id: 0 },
+        );
+        assert_eq!(contract.payment(0).installments, 3);
+        assert_eq!(contract.reserved, 3 * DEFAULT_TRANSFER);
+    }
+
+    #[test]
+    #[should_panic(expected = "recurring payment must have a count or an end")]
+    fn test_recurring_payment_unbounded() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::RecurringPayment {
+            recipient: accounts(3),
+            amount: DEFAULT_TRANSFER.into(),
+            start: 50.into(),
+            interval: 10,
+            count: None,
+            end: None,
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    #[should_panic(expected = "recurring payment must have at least one installment")]
+    fn test_recurring_payment_no_installments() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::RecurringPayment {
+            recipient: accounts(3),
+            amount: DEFAULT_TRANSFER.into(),
+            start: 50.into(),
+            interval: 10,
+            count: Some(3),
+            end: Some(40.into()),
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    #[should_panic(expected = "payment total overflows")]
+    fn test_recurring_payment_overflow() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::RecurringPayment {
+            recipient: accounts(3),
+            amount: u128::MAX.into(),
+            start: 50.into(),
+            interval: 10,
+            count: Some(2),
+            end: None,
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    fn test_vesting_grant() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, grant_action(50, false));
+        assert_eq!(contract.reserved, 10_000);
+
+        // before cliff
+        update_context(&mut ctx, 3, 0, 59);
+        assert_eq!(contract.grant(0).vested, 0.into());
+        update_context(&mut ctx, 3, 0, 60);
+        assert_eq!(contract.grant(0).vested, 1000.into());
+
+        update_context(&mut ctx, 3, 0, 100);
+        contract.withdraw_vested(0);
+        let g = contract.grant(0);
+        assert_eq!(g.withdrawn, 5000.into());
+        assert_eq!(contract.reserved, 5000);
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, 5000),
+            _ => panic!("invalid action type"),
+        }
+
+        update_context(&mut ctx, 3, 0, 1000);
+        contract.withdraw_vested(0);
+        assert_eq!(contract.grant(0).withdrawn, 10_000.into());
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing to withdraw")]
+    fn test_withdraw_vested_before_cliff() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, grant_action(50, false));
+        update_context(&mut ctx, 3, 0, 55);
+        contract.withdraw_vested(0);
+    }
+
+    #[test]
+    fn test_revoke_grant() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        // the grant is vested in 20% at the revocation time (40)
+        pass_and_execute(&mut ctx, &mut contract, grant_action(20, true));
+        pass_and_execute(&mut ctx, &mut contract, Action::RevokeGrant { grant_id: 0 });
+        let g = contract.grant(0);
+        assert_eq!(g.revoked_at, Some(40.into()));
+        assert_eq!(contract.reserved, 2000);
+
+        update_context(&mut ctx, 3, 0, 1000);
+        assert_eq!(contract.grant(0).vested, 2000.into());
+        contract.withdraw_vested(0);
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "grant is not revocable")]
+    fn test_revoke_not_revocable_grant() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, grant_action(50, false));
+        pass_and_execute(&mut ctx, &mut contract, Action::RevokeGrant { grant_id: 0 });
+    }
+
+    #[test]
+    #[should_panic(expected = "grant cliff must not be longer than the grant duration")]
+    fn test_grant_invalid_cliff() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::CreateGrant {
+            beneficiary: accounts(3),
+            total: 10_000.into(),
+            start: 50.into(),
+            cliff: 101,
+            duration: 100,
+            revocable: false,
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    fn test_spend_allowance() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        assert_eq!(
+            contract.allowance(accounts(1)),
+            Some(AllowanceOut {
+                amount: 10_000.into(),
+                expiry: 100.into()
+            })
+        );
+
+        update_context(&mut ctx, 1, BASE_UNIT * 10, 50);
+        contract.spend_allowance(accounts(3), 4000.into());
+        assert_eq!(contract.allowance(accounts(1)).unwrap().amount, 6000.into());
+        assert_eq!(
+            contract.spends(0, 10),
+            vec![SpendOut {
+                member: accounts(1).into(),
+                dest: accounts(3).into(),
+                amount: 4000.into(),
+                timestamp: 50.into(),
+            }]
+        );
+        let receipts = deserialize_receipts();
+        assert!(receipts
+            .iter()
+            .any(|r| r.receiver_id == AccountId::from(accounts(3))));
+
+        // expired allowance reserves funds until it's released
+        update_context(&mut ctx, 4, 0, 101);
+        let available = contract.available_balance().0;
+        contract.release_allowance(accounts(1));
+        assert_eq!(contract.allowance(accounts(1)), None);
+        assert_eq!(contract.available_balance().0 - available, 6000);
+    }
+
+    #[test]
+    fn test_spend_whole_allowance() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        update_context(&mut ctx, 1, BASE_UNIT * 10, 50);
+        contract.spend_allowance(accounts(3), 10_000.into());
+        assert_eq!(contract.allowance(accounts(1)), None);
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "allowance is not expired")]
+    fn test_release_active_allowance() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        update_context(&mut ctx, 4, 0, 100);
+        contract.release_allowance(accounts(1));
+    }
+
+    #[test]
+    fn test_removed_member_allowance() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        set_member_at(&mut ctx, &mut contract, 1, 0);
+        assert_eq!(contract.allowance(accounts(1)), None);
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough allowance (remaining: 10000, required: 10001)")]
+    fn test_spend_allowance_too_much() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        update_context(&mut ctx, 1, BASE_UNIT, 50);
+        contract.spend_allowance(accounts(3), 10_001.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "allowance expired")]
+    fn test_spend_allowance_expired() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, allowance_action(10_000));
+        update_context(&mut ctx, 1, BASE_UNIT, 101);
+        contract.spend_allowance(accounts(3), 1.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "allowance can be granted only to a member")]
+    fn test_grant_allowance_not_member() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::GrantAllowance {
+                member: accounts(3),
+                amount: 10.into(),
+                expiry: 100.into(),
+            },
+        );
+    }
+
+    #[test]
+    fn test_escrow() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, escrow_action(3));
+        assert_eq!(contract.reserved, 3000);
+
+        // alice power (2) is not enough to release a milestone
+        update_context(&mut ctx, 0, BASE_UNIT, 50);
+        contract.confirm_milestone(0, 0);
+        assert_eq!(contract.escrow(0).milestones[0].released, false);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"vostok-dao","version":"1.0.0","event":"milestone_confirmed","data":[{"escrow_id":0,"member":"alice","milestone":0,"support":2}]}"#
+            ]
+        );
+
+        update_context(&mut ctx, 1, BASE_UNIT, 50);
+        contract.confirm_milestone(0, 0);
+        let e = contract.escrow(0);
+        assert_eq!(e.milestones[0].released, true);
+        assert_eq!(e.milestones[0].support, 5);
+        assert_eq!(contract.reserved, 2000);
+        let receipts = deserialize_receipts();
+        let transfer = receipts
+            .iter()
+            .find(|r| r.receiver_id == AccountId::from(accounts(3)))
+            .expect("no transfer to the beneficiary");
+        match &transfer.actions[0] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, 1000),
+            _ => panic!("invalid action type"),
+        }
+
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::CancelEscrow { escrow_id: 0 },
+        );
+        assert_eq!(contract.escrow(0).cancelled, true);
+        assert_eq!(contract.reserved, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "you already confirmed")]
+    fn test_confirm_milestone_twice() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, escrow_action(3));
+        update_context(&mut ctx, 0, BASE_UNIT, 50);
+        contract.confirm_milestone(0, 1);
+        update_context(&mut ctx, 0, BASE_UNIT, 51);
+        contract.confirm_milestone(0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "you (danny) are not authorized to confirm")]
+    fn test_confirm_milestone_not_member() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, escrow_action(3));
+        update_context(&mut ctx, 3, BASE_UNIT, 50);
+        contract.confirm_milestone(0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "you (danny) are not authorized to confirm")]
+    fn test_confirm_milestone_member_added_later() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, escrow_action(3));
+        assert_eq!(contract.escrow(0).policy_version, 0);
+        set_member_at(&mut ctx, &mut contract, 3, 5);
+        assert_eq!(contract.member_power(accounts(3), 1), 5);
+        update_context(&mut ctx, 3, BASE_UNIT, 81);
+        contract.confirm_milestone(0, 0);
+    }
+
+    /// Sets the `account` power with a proposal created at 41 and executed at 80.
+    fn set_member_at(ctx: &mut VMContextBuilder, contract: &mut Contract, account: u8, power: u16) {
+        let action = Action::SetMember {
+            member: accounts(account.into()),
+            power,
+        };
+        pass_and_execute_at(ctx, contract, action, 41);
+    }
+
+    #[test]
+    #[should_panic(expected = "release_support must not be bigger than min_support")]
+    fn test_escrow_release_support_too_big() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(&mut ctx, &mut contract, escrow_action(6));
+    }
+
+    #[test]
+    fn test_migrate_from_v02() {
+        let mut ctx = VMContextBuilder::new();
+        testing_env!(ctx
+            .current_account_id(accounts(5))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(FROM_NANO)
+            .build());
+        let mut proposals: Vector<ProposalV02> = Vector::new("p".into());
+        let mut voters = HashSet::new();
+        voters.insert(AccountId::from(accounts(1)));
+        proposals.push(&ProposalV02 {
+            proposer: accounts(0).into(),
+            description: "transfer to danny".into(),
+            action: ActionInt::Transfer {
+                dest: accounts(3).into(),
+                amount: DEFAULT_TRANSFER,
+            },
+            voters,
+            voting_start: 10,
+            voting_end: 30,
+            votes_for: 3,
+            votes_against: 0,
+            execute_before: 100,
+            executed: false,
+        });
+        let old = ContractV02 {
+            deployer_id: accounts(0).into(),
+            members: vec![
+                Voter {
+                    account: accounts(0).into(),
+                    power: 2,
+                },
+                Voter {
+                    account: accounts(1).into(),
+                    power: 3,
+                },
+            ],
+            min_support: 5,
+            min_duration: 10,
+            max_duration: 20,
+            min_bond: BASE_UNIT,
+            next_idx: 1,
+            proposals,
+        };
+        env::state_write(&old);
+        assert_eq!(read_state_version(), StateVersion::V02);
+
+        testing_env!(ctx.predecessor_account_id(accounts(5)).build());
+        let mut contract = Contract::migrate();
+        assert_eq!(read_state_version(), STATE_VERSION);
+        // bob's power changes before his v0.2 vote is migrated.
+        contract.set_member(&accounts(1).into(), 4);
+        assert_eq!(contract.migrate_proposals(10), 0);
+        let c = contract.settings();
+        assert_eq!(c.deployer_id, "alice");
+        assert_eq!(
+            c.members,
+            "[{\"account\":\"alice\",\"power\":2},{\"account\":\"bob\",\"power\":4}]"
+        );
+        assert_eq!(c.min_support, 5);
+        assert_eq!(c.min_bond, U128::from(BASE_UNIT));
+        let p = contract.proposal(0);
+        assert_eq!(p.description, "transfer to danny");
+        assert_eq!(p.voting_end, 30.into());
+        assert_eq!(p.votes_for, 3);
+        // the migrated vote keeps the v0.2 power.
+        assert_eq!(
+            contract.proposal_vote(0, accounts(1)),
+            Some(Vote {
+                support: None,
+                power: 3,
+                delegated: false,
+            })
+        );
+
+        // migrated contract is fully functional
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+        update_context(&mut ctx, 4, 0, 40);
+        contract.execute(0);
+        assert_eq!(contract.proposal(0).executed, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal 0 is not migrated yet, use migrate_proposals")]
+    fn test_migrate_pending_proposal() {
+        let mut ctx = VMContextBuilder::new();
+        testing_env!(ctx.current_account_id(accounts(5)).build());
+        let mut proposals: Vector<ProposalV02> = Vector::new("p".into());
+        proposals.push(&ProposalV02 {
+            proposer: accounts(0).into(),
+            description: "transfer to danny".into(),
+            action: ActionInt::Transfer {
+                dest: accounts(3).into(),
+                amount: DEFAULT_TRANSFER,
+            },
+            voters: HashSet::new(),
+            voting_start: 10,
+            voting_end: 30,
+            votes_for: 0,
+            votes_against: 0,
+            execute_before: 100,
+            executed: false,
+        });
+        env::state_write(&ContractV02 {
+            deployer_id: accounts(0).into(),
+            members: vec![voter(0, 2)],
+            min_support: 2,
+            min_duration: 10,
+            max_duration: 20,
+            min_bond: BASE_UNIT,
+            next_idx: 1,
+            proposals,
+        });
+        testing_env!(ctx.predecessor_account_id(accounts(5)).build());
+        let contract = Contract::migrate();
+        contract.proposal(0);
+    }
+
+    #[test]
+    fn test_migrate_current_version() {
+        let (mut ctx, contract) = setup_contract(5);
+        env::state_write(&contract);
+        testing_env!(ctx
+            .current_account_id(accounts(5))
+            .predecessor_account_id(accounts(5))
+            .build());
+        let c = Contract::migrate();
+        assert_eq!(c.min_support, 5);
+        assert_eq!(c.members.len(), 3);
+        assert_eq!(c.total_power, 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "migrate can be called only by the contract account")]
+    fn test_migrate_not_authorized() {
+        let (mut ctx, contract) = setup_contract(5);
+        env::state_write(&contract);
+        update_context(&mut ctx, 1, 0, 1);
+        Contract::migrate();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let code = b"new contract code".to_vec();
+        testing_env!(ctx
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .input(code.clone())
+            .build());
+        let code_hash = contract.store_code();
+        assert_eq!(Vec::from(code_hash.clone()), env::sha256(&code));
+        assert!(contract.has_code(code_hash.clone()));
+
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::Upgrade {
+                code_hash: code_hash.clone(),
+            },
+        );
+        // the code is kept until the upgrade succeeds.
+        assert!(contract.has_code(code_hash.clone()));
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].actions.len(), 2);
+        match &receipts[0].actions[0] {
+            tutils::Action::DeployContract(d) => assert_eq!(d.code, code),
+            _ => panic!("invalid action type"),
+        }
+        match &receipts[0].actions[1] {
+            tutils::Action::FunctionCall(f) => assert_eq!(f.method_name, b"migrate".to_vec()),
+            _ => panic!("invalid action type"),
+        }
+
+        // the code storage is refunded to the uploader.
+        ctx.predecessor_account_id(accounts(0).into());
+        testing_env_with_promise_results(ctx.build(), PromiseResult::Successful(Vec::new()));
+        contract.on_execute(0);
+        assert!(!contract.has_code(code_hash));
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
+    }
+
+    #[test]
+    fn test_upgrade_failed() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .input(b"new contract code".to_vec())
+            .build());
+        let code_hash = contract.store_code();
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::Upgrade {
+                code_hash: code_hash.clone(),
+            },
+        );
+
+        ctx.predecessor_account_id(accounts(0).into());
+        testing_env_with_promise_results(ctx.build(), PromiseResult::Failed);
+        contract.on_execute(0);
+        // the code can be used by a new proposal or removed by the uploader.
+        assert!(contract.has_code(code_hash.clone()));
+        update_context(&mut ctx, 3, 1, 50);
+        contract.remove_code(code_hash.clone());
+        assert!(!contract.has_code(code_hash));
+    }
+
+    #[test]
+    fn test_remove_code() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .input(b"new contract code".to_vec())
+            .build());
+        let code_hash = contract.store_code();
+
+        update_context(&mut ctx, 3, 1, 1);
+        contract.remove_code(code_hash.clone());
+        assert!(!contract.has_code(code_hash));
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert!(t.deposit > 0),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "only the uploader (danny) can remove the code")]
+    fn test_remove_code_not_uploader() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .input(b"new contract code".to_vec())
+            .build());
+        let code_hash = contract.store_code();
+        update_context(&mut ctx, 1, 1, 1);
+        contract.remove_code(code_hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "code not found, use store_code first")]
+    fn test_upgrade_code_not_stored() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::Upgrade {
+            code_hash: env::sha256(b"unknown code").into(),
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    fn test_access_keys() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let key = Base58PublicKey(vec![0; 33]);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::AddFullAccessKey {
+                public_key: key.clone(),
+            },
+        );
+        let receipts = deserialize_receipts();
+        match &receipts[0].actions[0] {
+            tutils::Action::AddKeyWithFullAccess(a) => assert_eq!(a.public_key, key.0),
+            _ => panic!("invalid action type"),
+        }
+
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::AddFunctionCallKey {
+                public_key: key.clone(),
+                allowance: ONE_NEAR.into(),
+                receiver_id: accounts(4),
+                method_names: vec!["vote".into(), "execute".into()],
+            },
+        );
+        let receipts = deserialize_receipts();
+        match &receipts[0].actions[0] {
+            tutils::Action::AddKeyWithFunctionCall(a) => {
+                assert_eq!(a.public_key, key.0);
+                assert_eq!(a.allowance, Some(ONE_NEAR));
+                assert_eq!(a.receiver_id, AccountId::from(accounts(4)));
+                assert_eq!(a.method_names, vec![b"vote".to_vec(), b"execute".to_vec()]);
+            }
+            _ => panic!("invalid action type"),
+        }
+
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::DeleteKey {
+                public_key: key.clone(),
+            },
+        );
+        let receipts = deserialize_receipts();
+        match &receipts[0].actions[0] {
+            tutils::Action::DeleteKey(a) => assert_eq!(a.public_key, key.0),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "method name \"vote,execute\" is not valid")]
+    fn test_function_call_key_invalid_method() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::AddFunctionCallKey {
+            public_key: Base58PublicKey(vec![0; 33]),
+            allowance: 0.into(),
+            receiver_id: accounts(4),
+            method_names: vec!["vote,execute".into()],
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    fn test_create_sub_account() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let code = b"grants contract code".to_vec();
+        testing_env!(ctx
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .input(code.clone())
+            .build());
+        let code_hash = contract.store_code();
+
+        let key = Base58PublicKey(vec![0; 33]);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::CreateSubAccount {
+                name: "grants".into(),
+                amount: ONE_NEAR.into(),
+                public_key: Some(key.clone()),
+                code_hash: Some(code_hash),
+            },
+        );
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(
+            receipts[0].receiver_id,
+            format!("grants.{}", env::current_account_id())
+        );
+        let actions = &receipts[0].actions;
+        assert_eq!(actions.len(), 4);
+        assert!(matches!(actions[0], tutils::Action::CreateAccount));
+        match &actions[1] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, ONE_NEAR),
+            _ => panic!("invalid action type"),
+        }
+        match &actions[2] {
+            tutils::Action::AddKeyWithFullAccess(a) => assert_eq!(a.public_key, key.0),
+            _ => panic!("invalid action type"),
+        }
+        match &actions[3] {
+            tutils::Action::DeployContract(d) => assert_eq!(d.code, code),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sub-account name \"a.b\" is not valid")]
+    fn test_create_sub_account_invalid_name() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::CreateSubAccount {
+            name: "a.b".into(),
+            amount: ONE_NEAR.into(),
+            public_key: None,
+            code_hash: None,
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    fn test_storage_deposit() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let min: Balance = contract.storage_balance_bounds().min.into();
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+
+        update_context(&mut ctx, 1, ONE_NEAR, 1);
+        let b = contract.storage_deposit(None, None);
+        assert_eq!(b.total, ONE_NEAR.into());
+        assert_eq!(b.available, (ONE_NEAR - min).into());
+        // deposit for another account, only the minimum is taken.
+        let b = contract.storage_deposit(Some(accounts(2)), Some(true));
+        assert_eq!(b.total, min.into());
+        assert_eq!(contract.storage_balance_of(accounts(2)), Some(b));
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(1)));
+
+        update_context(&mut ctx, 1, 1, 1);
+        let b = contract.storage_withdraw(Some(1000.into()));
+        assert_eq!(b.total, (ONE_NEAR - 1000).into());
+        let b = contract.storage_withdraw(None);
+        assert_eq!(b.total, min.into());
+        assert_eq!(b.available, 0.into());
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+        let logs = get_logs();
+        assert_eq!(logs.len(), 3);
+        assert!(logs[0].contains(r#""event":"storage_withdraw""#));
+        assert_eq!(
+            logs[2],
+            r#"EVENT_JSON:{"standard":"vostok-dao","version":"1.0.0","event":"storage_unregister","data":[{"account":"bob","amount":"1250000000000000000000","balance":"0"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_vote_from_storage_balance() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 1, ONE_NEAR, 5);
+        contract.storage_deposit(None, None);
+
+        update_context(&mut ctx, 1, 0, 10);
+        contract.vote(0, true);
+        assert_eq!(deserialize_receipts().len(), 0, "no refund expected");
+        let b = contract.storage_balance_of(accounts(1)).unwrap();
+        assert!(Balance::from(b.total) < ONE_NEAR);
+        assert_eq!(contract.proposal(0).votes_for, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough storage balance (available: 0")]
+    fn test_vote_storage_balance_too_low() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        update_context(&mut ctx, 1, ONE_NEAR, 5);
+        contract.storage_deposit(None, Some(true));
+
+        update_context(&mut ctx, 1, 0, 10);
+        contract.vote(0, true);
+    }
+
+    #[test]
+    fn test_vote_sponsorship() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let cap = BASE_UNIT * 5;
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetVoteSponsorship {
+                cap: cap.into(),
+                period: 100,
+            },
+        );
+        assert_eq!(contract.settings().vote_sponsorship_cap, cap.into());
+        assert_eq!(contract.sponsored_storage_left(accounts(1)), cap.into());
+
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        let mut p = sample_proposal();
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        contract.add_proposal(p.clone());
+        contract.add_proposal(p);
+
+        // the vote is sponsored and the whole deposit is refunded.
+        update_context(&mut ctx, 1, BASE_UNIT * 5, 55);
+        contract.vote(1, true);
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts.len(), 1);
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, BASE_UNIT * 5),
+            _ => panic!("invalid action type"),
+        }
+        let left: Balance = contract.sponsored_storage_left(accounts(1)).into();
+        assert!(left < cap);
+
+        // the cap is exhausted, bob pays for the second vote.
+        update_context(&mut ctx, 1, BASE_UNIT * 5, 56);
+        contract.vote(2, true);
+        assert_eq!(contract.sponsored_storage_left(accounts(1)), left.into());
+        let receipts = deserialize_receipts();
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert!(t.deposit < BASE_UNIT * 5),
+            _ => panic!("invalid action type"),
+        }
+
+        // the cap is renewed in the next period.
+        update_context(&mut ctx, 1, 0, 100);
+        assert_eq!(contract.sponsored_storage_left(accounts(1)), cap.into());
+
+        // sponsored vote storage returns to the treasury, bob's own storage to bob.
+        update_context(&mut ctx, 4, 0, 201);
+        contract.archive_proposal(1, 10);
+        assert_eq!(receivers(), vec![AccountId::from(accounts(0))]);
+        update_context(&mut ctx, 4, 0, 202);
+        contract.archive_proposal(2, 10);
+        assert_eq!(
+            receivers(),
+            vec![AccountId::from(accounts(0)), AccountId::from(accounts(1))]
+        );
+    }
+
+    #[test]
+    fn test_vote_sponsorship_non_member() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetVoteSponsorship {
+                cap: (BASE_UNIT * 5).into(),
+                period: 100,
+            },
+        );
+        let action = Action::SetTokenVoting {
+            token: accounts(5),
+            power_unit: 1000.into(),
+        };
+        pass_and_execute_at(&mut ctx, &mut contract, action, 41);
+        // danny is not a member, but votes with staked tokens.
+        update_context(&mut ctx, 3, ONE_NEAR, 81);
+        contract.storage_deposit(None, None);
+        update_context(&mut ctx, 5, 0, 81);
+        contract.ft_on_transfer(accounts(3), 5000.into(), "".into());
+        let mut p = sample_proposal();
+        p.voting_start = 90.into();
+        p.execute_before = 300.into();
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 81);
+        let id = contract.add_proposal(p);
+
+        update_context(&mut ctx, 3, BASE_UNIT * 5, 95);
+        contract.vote(id, true);
+        // danny pays for his vote storage.
+        let e = contract.vote_index.get(&(id, 0)).unwrap();
+        assert_eq!(e.payer, Some(accounts(3).into()));
+        assert!(contract.sponsored.get(&accounts(3).into()).is_none());
+    }
+
+    #[test]
+    fn test_delegation() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 1, BASE_UNIT * 20, 0);
+        contract.delegate(accounts(2));
+        assert_eq!(contract.delegation(accounts(1)), Some(accounts(2).into()));
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(sample_proposal());
+        assert_eq!(
+            contract.delegators(accounts(2), Some(0)),
+            vec![AccountId::from(accounts(1))]
+        );
+
+        // charlie casts bob's power as well.
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 10);
+        contract.vote(0, true);
+        assert_eq!(contract.proposal(0).votes_for, 7);
+        assert_eq!(
+            contract.proposal_vote(0, accounts(1)),
+            Some(Vote {
+                support: Some(true),
+                power: 3,
+                delegated: true,
+            })
+        );
+
+        // bob overrides the delegated vote.
+        update_context(&mut ctx, 1, BASE_UNIT * 10, 11);
+        contract.vote(0, false);
+        let p = contract.proposal(0);
+        assert_eq!(p.votes_for, 4);
+        assert_eq!(p.votes_against, 3);
+        assert_eq!(
+            contract.proposal_vote(0, accounts(1)).unwrap().delegated,
+            false
+        );
+    }
+
+    #[test]
+    fn test_delegation_snapshot() {
+        let (mut ctx, mut contract, _p) = setup_with_proposal();
+        // alice votes before her delegate.
+        update_context(&mut ctx, 0, BASE_UNIT * 20, 2);
+        contract.delegate(accounts(2));
+        update_context(&mut ctx, 1, BASE_UNIT * 20, 2);
+        contract.delegate(accounts(2));
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 3);
+        let mut p = sample_proposal();
+        p.voting_start = 5.into();
+        contract.add_proposal(p);
+        update_context(&mut ctx, 0, BASE_UNIT * 10, 6);
+        contract.vote(1, false);
+
+        // the delegation doesn't apply to proposal 0, created before.
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 10);
+        contract.vote(0, true);
+        contract.vote(1, true);
+        assert_eq!(contract.proposal(0).votes_for, 4);
+        let p = contract.proposal(1);
+        assert_eq!(p.votes_for, 7);
+        assert_eq!(p.votes_against, 2);
+
+        // undelegation applies to new proposals only.
+        update_context(&mut ctx, 1, BASE_UNIT, 11);
+        contract.undelegate();
+        assert_eq!(contract.delegation(accounts(1)), None);
+        assert_eq!(
+            contract.delegators(accounts(2), Some(1)),
+            vec![AccountId::from(accounts(0)), AccountId::from(accounts(1))]
+        );
+        assert_eq!(
+            contract.delegators(accounts(2), None),
+            vec![AccountId::from(accounts(0))]
+        );
+    }
+
+    #[test]
+    fn test_delegation_pruning() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 1, BASE_UNIT * 20, 0);
+        contract.delegate(accounts(2));
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.description = "transfer to danny ".repeat(10);
+        contract.add_proposal(p);
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 10);
+        contract.vote(0, true);
+
+        // the ended delegation is kept while proposal 0 voting is open.
+        update_context(&mut ctx, 1, BASE_UNIT, 11);
+        contract.undelegate();
+        update_context(&mut ctx, 0, BASE_UNIT * 20, 12);
+        contract.delegate(accounts(2));
+        assert_eq!(
+            contract.delegators(accounts(2), Some(0)),
+            vec![AccountId::from(accounts(1))]
+        );
+
+        // it's pruned once the voting ends.
+        update_context(&mut ctx, 0, BASE_UNIT, 31);
+        contract.undelegate();
+        assert!(contract.delegators(accounts(2), Some(0)).is_empty());
+        assert!(contract.delegators.get(&accounts(2).into()).is_none());
+
+        // charlie paid for bob's delegated vote and gets its storage back.
+        update_context(&mut ctx, 4, 0, 101);
+        assert_eq!(contract.archive_proposal(0, 10), 0);
+        assert_eq!(
+            receivers(),
+            vec![
+                AccountId::from(accounts(0)),
+                AccountId::from(accounts(2)),
+                AccountId::from(accounts(2))
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "you can't delegate to yourself")]
+    fn test_delegate_to_yourself() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 1, BASE_UNIT * 20, 0);
+        contract.delegate(accounts(1));
+    }
+
+    /// Adds danny (power 5) by proposal 0, executed at 31. Proposal 1 is created before and
+    /// proposal 2 after the change, both with voting between 35 and 55.
+    fn setup_member_change() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::SetMember {
+            member: accounts(3),
+            power: 5,
+        };
+        contract.add_proposal(p);
+        vote_alice_and_charile(&mut ctx, &mut contract);
+
+        let mut p = sample_proposal();
+        p.voting_start = 35.into();
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 20);
+        contract.add_proposal(p.clone());
+        update_context(&mut ctx, 4, 0, 31);
+        contract.execute(0);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 32);
+        contract.add_proposal(p);
+        (ctx, contract)
+    }
+
+    #[test]
+    fn test_membership_snapshot() {
+        let (mut ctx, mut contract) = setup_member_change();
+        assert_eq!(contract.settings().total_power, 14);
+        assert_eq!(
+            contract.policy(1),
+            Policy {
+                min_support: 5,
+                total_power: 14,
+                token_power_unit: None,
+                tally: Tally::Linear,
+            }
+        );
+        assert_eq!(contract.member_power(accounts(3), 0), 0);
+        assert_eq!(contract.member_power(accounts(3), 1), 5);
+        assert_eq!(contract.member_power(accounts(1), 1), 3);
+        assert_eq!(contract.proposal(1).policy_version, 0);
+        assert_eq!(contract.proposal(2).policy_version, 1);
+
+        update_context(&mut ctx, 3, BASE_UNIT * 4, 40);
+        contract.vote(2, true);
+        assert_eq!(contract.proposal(2).votes_for, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "you (danny) are not authorized to vote")]
+    fn test_membership_snapshot_new_member() {
+        let (mut ctx, mut contract) = setup_member_change();
+        update_context(&mut ctx, 3, BASE_UNIT * 4, 40);
+        contract.vote(1, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "min_support (5) is bigger than the total members power (4)")]
+    fn test_remove_member_min_support() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetMember {
+                member: accounts(1),
+                power: 0,
+            },
+        );
+        let mut p = sample_proposal();
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        p.action = Action::SetMember {
+            member: accounts(0),
+            power: 0,
+        };
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        contract.add_proposal(p);
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 50);
+        contract.vote(1, true);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 50);
+        contract.vote(1, true);
+        update_context(&mut ctx, 4, 0, 80);
+        contract.execute(1);
+    }
+
+    /// Enables the token voting with fargo tokens, 1000 tokens per power, and stakes 5000
+    /// tokens for bob. Creates proposal 1 (voting between 50 and 70) under the token voting.
+    fn setup_token_voting() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetTokenVoting {
+                token: accounts(5),
+                power_unit: 1000.into(),
+            },
+        );
+        update_context(&mut ctx, 1, ONE_NEAR, 41);
+        contract.storage_deposit(None, None);
+        update_context(&mut ctx, 5, 0, 41);
+        contract.ft_on_transfer(accounts(1), 5000.into(), "".into());
+
+        let mut p = sample_proposal();
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        contract.add_proposal(p);
+        (ctx, contract)
+    }
+
+    #[test]
+    fn test_archive_token_vote() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 55);
+        contract.vote(1, true);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap().total.0;
+        update_context(&mut ctx, 4, 0, 201);
+        assert_eq!(contract.archive_proposal(1, 10), 0);
+        assert_eq!(contract.proposal_vote(1, accounts(1)), None);
+        // the vote storage is credited to bob's storage balance.
+        assert!(contract.storage_balance_of(accounts(1)).unwrap().total.0 > balance);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't delete the account while storage balances exist")]
+    fn test_delete_with_storage_balances() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 1, ONE_NEAR, 0);
+        contract.storage_deposit(None, None);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::Delete { dest: accounts(3) },
+        );
+    }
+
+    #[test]
+    fn test_token_voting() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        assert_eq!(contract.policy(1).token_power_unit, Some(U128::from(1000)));
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 55);
+        contract.vote(1, true);
+        assert_eq!(contract.proposal(1).votes_for, 5);
+        assert_eq!(
+            contract.stake(accounts(1)),
+            Some(StakeOut {
+                amount: 5000.into(),
+                locked_until: 70.into(),
+            })
+        );
+
+        // the stake is unlocked after the voting end.
+        update_context(&mut ctx, 1, 1, 71);
+        contract.unstake(2000.into());
+        assert_eq!(contract.stake(accounts(1)).unwrap().amount, 3000.into());
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(5)));
+        match &receipts[0].actions[0] {
+            tutils::Action::FunctionCall(f) => assert_eq!(f.method_name, b"ft_transfer"),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The account has staked tokens, unstake them or use force")]
+    fn test_storage_unregister_with_stake() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 1, 1, 60);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_storage_unregister_force() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 1, 1, 60);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+        assert_eq!(contract.stake(accounts(1)).unwrap().amount, 5000.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "stake is locked until 70")]
+    fn test_unstake_locked() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 55);
+        contract.vote(1, true);
+        update_context(&mut ctx, 1, 1, 60);
+        contract.unstake(1000.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "you (alice) are not authorized to vote")]
+    fn test_token_voting_member_without_stake() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 55);
+        contract.vote(1, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "only the staking token can be staked")]
+    fn test_stake_other_token() {
+        let (mut ctx, mut contract) = setup_token_voting();
+        update_context(&mut ctx, 4, 0, 60);
+        contract.ft_on_transfer(accounts(1), 5000.into(), "".into());
+    }
+
+    /// Switches to the quadratic tally with 10 credits per power and 1000 seconds epochs,
+    /// and creates proposals 1 and 2 with voting between 50 and 70.
+    fn setup_quadratic() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetTally {
+                tally: Tally::Quadratic {
+                    credits_per_power: 10,
+                    epoch: 1000,
+                },
+            },
+        );
+        let mut p = sample_proposal();
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        contract.add_proposal(p.clone());
+        contract.add_proposal(p);
+        (ctx, contract)
+    }
+
+    #[test]
+    fn test_quadratic_voting() {
+        let (mut ctx, mut contract) = setup_quadratic();
+        // charlie has 40 credits and bob 30.
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 55);
+        contract.vote_with_credits(1, true, 16);
+        update_context(&mut ctx, 1, BASE_UNIT * 10, 55);
+        contract.vote_with_credits(1, false, 10);
+        let p = contract.proposal(1);
+        assert_eq!(p.votes_for, 4);
+        assert_eq!(p.votes_against, 3);
+        assert_eq!(
+            contract.voice_credits(accounts(2)),
+            Some(VoiceCreditsOut {
+                epoch: 0.into(),
+                spent: 16.into(),
+            })
+        );
+
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 56);
+        contract.vote_with_credits(2, true, 24);
+        assert_eq!(contract.proposal(2).votes_for, 4);
+        assert_eq!(
+            contract.voice_credits(accounts(2)).unwrap().spent,
+            40.into()
+        );
+    }
+
+    #[test]
+    fn test_quadratic_voting_pass() {
+        let (mut ctx, mut contract) = setup_quadratic();
+        assert_eq!(contract.policy(contract.policy_version()).min_votes(), 15);
+        // all members spend their credits: 4 + 5 + 6 votes.
+        for (voter, credits) in [(0, 20), (1, 30), (2, 40)].iter() {
+            update_context(&mut ctx, *voter, BASE_UNIT * 10, 55);
+            contract.vote_with_credits(1, true, *credits);
+        }
+        assert_eq!(contract.proposal(1).votes_for, 15);
+
+        update_context(&mut ctx, 4, 0, 71);
+        contract.execute(1);
+        assert!(contract.proposal(1).executed);
+        assert_transferred(DEFAULT_TRANSFER);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "members can't reach min_support in the quadratic tally (votes: 4, required: 5)"
+    )]
+    fn test_quadratic_tally_unreachable() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = Action::SetTally {
+            tally: Tally::Quadratic {
+                credits_per_power: 1,
+                epoch: 1000,
+            },
+        };
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough voice credits (available: 24, required: 25)")]
+    fn test_quadratic_voting_not_enough_credits() {
+        let (mut ctx, mut contract) = setup_quadratic();
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 55);
+        contract.vote_with_credits(1, true, 16);
+        contract.vote_with_credits(2, true, 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal uses the quadratic tally, use vote_with_credits")]
+    fn test_quadratic_voting_linear_vote() {
+        let (mut ctx, mut contract) = setup_quadratic();
+        update_context(&mut ctx, 2, BASE_UNIT * 10, 55);
+        contract.vote(1, true);
+    }
+
+    #[test]
+    fn test_isqrt() {
+        let roots: Vec<u64> = [0, 1, 3, 4, 15, 16, 17, 1_000_000, u64::MAX]
+            .iter()
+            .map(|n| isqrt(*n))
+            .collect();
+        assert_eq!(roots, vec![0, 1, 1, 2, 3, 4, 4, 1000, u32::MAX.into()]);
+    }
+
+    /// Multi-option proposal transferring 1, 2 or 3 times `DEFAULT_TRANSFER` to danny.
+    fn choice_proposal(method: ChoiceMethod) -> NewProposal {
+        let mut p = sample_proposal();
+        p.action = Action::Choice {
+            options: (1..=3)
+                .map(|n| Action::Transfer {
+                    dest: accounts(3),
+                    amount: (n * DEFAULT_TRANSFER).into(),
+                })
+                .collect(),
+            method,
+        };
+        p
+    }
+
+    fn assert_transferred(amount: Balance) {
+        let receipts = deserialize_receipts();
+        assert_eq!(receipts[0].receiver_id, AccountId::from(accounts(3)));
+        match &receipts[0].actions[0] {
+            tutils::Action::Transfer(t) => assert_eq!(t.deposit, amount),
+            _ => panic!("invalid action type"),
+        }
+    }
+
+    #[test]
+    fn test_plurality_proposal() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(choice_proposal(ChoiceMethod::Plurality));
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![0]);
+        assert!(contract.commitments.get(&0).is_none());
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![1]);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![1]);
+        assert_eq!(
+            contract.proposal_ballots(0),
+            vec![(vec![0], 2), (vec![1], 7)]
+        );
+        assert_eq!(contract.proposal(0).votes_for, 9);
+        assert_eq!(
+            contract.commitments.get(&0).unwrap().amount,
+            2 * DEFAULT_TRANSFER
+        );
+
+        update_context(&mut ctx, 4, 0, 31);
+        contract.execute(0);
+        assert!(contract.proposal(0).executed);
+        assert_eq!(contract.proposal(0).executed_option, Some(1));
+        assert_transferred(2 * DEFAULT_TRANSFER);
+    }
+
+    #[test]
+    fn test_ranked_choice_proposal() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(choice_proposal(ChoiceMethod::RankedChoice));
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![2, 0]);
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![0]);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![1, 0]);
+        // option 1 leads the first choices, but alice's ballot moves to option 0 after
+        // option 2 is eliminated.
+        assert_eq!(
+            contract.commitments.get(&0).unwrap().amount,
+            DEFAULT_TRANSFER
+        );
+
+        update_context(&mut ctx, 4, 0, 31);
+        contract.execute(0);
+        assert_transferred(DEFAULT_TRANSFER);
+
+        // rankings are removed and their storage refunded, the executed option is kept.
+        update_context(&mut ctx, 4, 0, 32);
+        assert_eq!(contract.archive_proposal(0, 10), 0);
+        assert!(contract.ballots.get(&(0, accounts(0).into())).is_none());
+        assert!(contract.proposal_ballots(0).is_empty());
+        assert_eq!(contract.proposal(0).executed_option, Some(0));
+        let receipts = deserialize_receipts();
+        assert!(receipts
+            .iter()
+            .any(|r| r.receiver_id == AccountId::from(accounts(2))));
+    }
+
+    #[test]
+    fn test_choice_winner() {
+        let ballots = vec![(vec![0, 1], 3), (vec![1], 3), (vec![2, 1], 2)];
+        assert_eq!(winner(ChoiceMethod::Plurality, 3, &ballots, 1), None);
+        assert_eq!(winner(ChoiceMethod::RankedChoice, 3, &ballots, 5), Some(1));
+        assert_eq!(winner(ChoiceMethod::RankedChoice, 3, &ballots, 6), None);
+        assert_eq!(winner(ChoiceMethod::RankedChoice, 3, &[], 1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "no option reached the required support (5)")]
+    fn test_choice_proposal_no_winner() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(choice_proposal(ChoiceMethod::Plurality));
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![0]);
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![1]);
+        update_context(&mut ctx, 4, 0, 31);
+        contract.execute(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "option 1 ranked twice")]
+    fn test_vote_option_invalid_ranking() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(choice_proposal(ChoiceMethod::RankedChoice));
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote_option(0, vec![1, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "use vote_option for multi-option proposals")]
+    fn test_vote_choice_proposal() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(choice_proposal(ChoiceMethod::Plurality));
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+    }
+
+    /// Creates a proposal with secret ballots: commits between 10 and 30, reveals until 50.
+    fn setup_secret_ballot() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.reveal_duration = Some(20);
+        contract.add_proposal(p);
+        (ctx, contract)
+    }
+
+    fn commit(ctx: &mut VMContextBuilder, contract: &mut Contract, voter: u8, support: bool) {
+        update_context(ctx, voter, BASE_UNIT * 5, 10);
+        let hash = contract.ballot_hash(0, accounts(voter.into()), support, vec![voter; 32].into());
+        contract.commit_vote(0, hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "salt must have at least 32 bytes")]
+    fn test_reveal_short_salt() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        update_context(&mut ctx, 0, BASE_UNIT * 5, 10);
+        let mut data = 0u32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"alice");
+        data.push(1);
+        data.extend_from_slice(&[0; 8]);
+        contract.commit_vote(0, env::sha256(&data).into());
+        update_context(&mut ctx, 0, 0, 35);
+        contract.reveal_vote(0, true, vec![0; 8].into());
+    }
+
+    #[test]
+    fn test_secret_ballot() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        commit(&mut ctx, &mut contract, 0, true);
+        commit(&mut ctx, &mut contract, 1, false);
+        commit(&mut ctx, &mut contract, 2, true);
+        assert_eq!(contract.proposal(0).votes_for, 0);
+        assert_eq!(contract.proposal(0).reveal_end, Some(50.into()));
+
+        // bob doesn't reveal the vote.
+        update_context(&mut ctx, 0, 0, 35);
+        contract.reveal_vote(0, true, vec![0; 32].into());
+        update_context(&mut ctx, 2, 0, 50);
+        contract.reveal_vote(0, true, vec![2; 32].into());
+        let p = contract.proposal(0);
+        assert_eq!(p.votes_for, 6);
+        assert_eq!(p.votes_against, 0);
+        assert_eq!(
+            contract.proposal_vote(0, accounts(0)),
+            Some(Vote {
+                support: Some(true),
+                power: 2,
+                delegated: false,
+            })
+        );
+
+        update_context(&mut ctx, 4, 0, 51);
+        contract.execute(0);
+        assert!(contract.proposal(0).executed);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal can be executed only between 51 and 100 timestamp")]
+    fn test_execute_secret_ballot_during_reveal() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        commit(&mut ctx, &mut contract, 0, true);
+        commit(&mut ctx, &mut contract, 2, true);
+        update_context(&mut ctx, 0, 0, 35);
+        contract.reveal_vote(0, true, vec![0; 32].into());
+        update_context(&mut ctx, 2, 0, 35);
+        contract.reveal_vote(0, true, vec![2; 32].into());
+        update_context(&mut ctx, 4, 0, 40);
+        contract.execute(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "vote doesn't match the commit")]
+    fn test_reveal_vote_mismatch() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        commit(&mut ctx, &mut contract, 0, true);
+        update_context(&mut ctx, 0, 0, 35);
+        contract.reveal_vote(0, false, vec![0; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "vote doesn't match the commit")]
+    fn test_reveal_copied_commit() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        commit(&mut ctx, &mut contract, 0, true);
+        // bob copies alice's commit, but can't reveal it with alice's salt.
+        let hash = contract.ballot_hash(0, accounts(0), true, vec![0; 32].into());
+        update_context(&mut ctx, 1, BASE_UNIT * 5, 10);
+        contract.commit_vote(0, hash);
+        update_context(&mut ctx, 1, 0, 35);
+        contract.reveal_vote(0, true, vec![0; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "reveal is not active")]
+    fn test_reveal_vote_during_voting() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        commit(&mut ctx, &mut contract, 0, true);
+        update_context(&mut ctx, 0, 0, 20);
+        contract.reveal_vote(0, true, vec![0; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal uses secret ballots, use commit_vote")]
+    fn test_vote_secret_ballot() {
+        let (mut ctx, mut contract) = setup_secret_ballot();
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+    }
+
+    /// Registers a vote key, derived from the account index, for the account.
+    fn set_vote_key(ctx: &mut VMContextBuilder, contract: &mut Contract, account: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[account + 1; 32]).unwrap();
+        let keypair = Keypair {
+            public: (&secret).into(),
+            secret,
+        };
+        let mut public_key = vec![0];
+        public_key.extend_from_slice(keypair.public.as_bytes());
+        update_context(ctx, account, BASE_UNIT * 5, 1);
+        contract.set_vote_key(Some(Base58PublicKey(public_key)));
+        keypair
+    }
+
+    fn signed_vote(contract: &Contract, keypair: &Keypair, voter: u8, nonce: u64) -> SignedVote {
+        let message = contract.vote_message(accounts(voter.into()), 0, true, nonce.into());
+        SignedVote {
+            voter: accounts(voter.into()),
+            proposal_id: 0,
+            support: true,
+            nonce: nonce.into(),
+            signature: keypair.sign(&message.0).to_bytes().to_vec().into(),
+        }
+    }
+
+    #[test]
+    fn test_submit_votes() {
+        let (mut ctx, mut contract, _) = setup_with_proposal();
+        let alice = set_vote_key(&mut ctx, &mut contract, 0);
+        let charlie = set_vote_key(&mut ctx, &mut contract, 2);
+        let logs = get_logs();
+        assert!(logs[0].contains(r#""event":"vote_key_changed""#));
+        assert!(logs[0].contains(r#""voter":"charlie""#));
+        let votes = vec![
+            signed_vote(&contract, &alice, 0, 1),
+            signed_vote(&contract, &charlie, 2, 7),
+        ];
+        // the relayer doesn't need to be a member.
+        update_context(&mut ctx, 4, BASE_UNIT * 10, 10);
+        contract.submit_votes(votes);
+        let p = contract.proposal(0);
+        assert_eq!(p.votes_for, 6);
+        assert_eq!(contract.vote_key(accounts(2)).unwrap().nonce, 7.into());
+        assert!(contract.proposal_vote(0, accounts(0)).is_some());
+
+        // the relayer paid for the votes storage and gets it back.
+        update_context(&mut ctx, 4, 0, 31);
+        contract.execute(0);
+        update_context(&mut ctx, 4, 0, 32);
+        assert_eq!(contract.archive_proposal(0, 10), 0);
+        assert_eq!(
+            receivers(),
+            vec![AccountId::from(accounts(4)), AccountId::from(accounts(4))]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "votes batch must have between 1 and 10 votes")]
+    fn test_submit_votes_batch_too_big() {
+        let (mut ctx, mut contract, _) = setup_with_proposal();
+        let alice = set_vote_key(&mut ctx, &mut contract, 0);
+        let votes = (1..=11)
+            .map(|nonce| signed_vote(&contract, &alice, 0, nonce))
+            .collect();
+        update_context(&mut ctx, 4, BASE_UNIT * 10, 10);
+        contract.submit_votes(votes);
+    }
+
+    #[test]
+    #[should_panic(expected = "nonce 1 of alice was already used")]
+    fn test_submit_votes_replay() {
+        let (mut ctx, mut contract, _) = setup_with_proposal();
+        let alice = set_vote_key(&mut ctx, &mut contract, 0);
+        update_context(&mut ctx, 4, BASE_UNIT * 10, 10);
+        contract.submit_votes(vec![signed_vote(&contract, &alice, 0, 1)]);
+        contract.submit_votes(vec![signed_vote(&contract, &alice, 0, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "vote signature of alice is not valid")]
+    fn test_submit_votes_invalid_signature() {
+        let (mut ctx, mut contract, _) = setup_with_proposal();
+        set_vote_key(&mut ctx, &mut contract, 0);
+        let charlie = set_vote_key(&mut ctx, &mut contract, 2);
+        update_context(&mut ctx, 4, BASE_UNIT * 10, 10);
+        contract.submit_votes(vec![signed_vote(&contract, &charlie, 0, 1)]);
+    }
+
+    /// Enables optimistic proposals with objection threshold 4 and `DEFAULT_TRANSFER` cap and
+    /// creates an optimistic transfer proposal 1 with voting between 50 and 70.
+    fn setup_optimistic() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetObjectionThreshold {
+                threshold: 4,
+                max_amount: DEFAULT_TRANSFER.into(),
+            },
+        );
+        assert_eq!(contract.settings().objection_threshold, 4);
+        assert_eq!(
+            contract.settings().optimistic_max_amount,
+            DEFAULT_TRANSFER.into()
+        );
+        let mut p = sample_proposal();
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        p.optimistic = true;
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        contract.add_proposal(p);
+        (ctx, contract)
+    }
+
+    #[test]
+    fn test_optimistic_proposal() {
+        let (mut ctx, mut contract) = setup_optimistic();
+        assert_eq!(contract.proposal(1).objection_threshold, Some(4));
+        // funds are committed since the creation.
+        assert_eq!(
+            contract.commitments.get(&1).unwrap().amount,
+            DEFAULT_TRANSFER
+        );
+        update_context(&mut ctx, 1, BASE_UNIT * 4, 55);
+        contract.vote(1, false);
+
+        update_context(&mut ctx, 4, 0, 71);
+        contract.execute(1);
+        assert!(contract.proposal(1).executed);
+        assert_transferred(DEFAULT_TRANSFER);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal was objected (votes_against: 4, objection_threshold: 4)")]
+    fn test_optimistic_proposal_objected() {
+        let (mut ctx, mut contract) = setup_optimistic();
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 55);
+        contract.vote(1, false);
+        assert!(contract.commitments.get(&1).is_none());
+        update_context(&mut ctx, 4, 0, 71);
+        contract.execute(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "optimistic proposals are disabled")]
+    fn test_optimistic_proposals_disabled() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let mut p = sample_proposal();
+        p.optimistic = true;
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(p);
+    }
+
+    /// Creates an optimistic proposal by the `account` at 41.
+    fn add_optimistic(
+        ctx: &mut VMContextBuilder,
+        contract: &mut Contract,
+        account: u8,
+        mut p: NewProposal,
+    ) {
+        p.voting_start = 50.into();
+        p.execute_before = 200.into();
+        p.optimistic = true;
+        update_context(ctx, account, BASE_UNIT * 300, 41);
+        contract.add_proposal(p);
+    }
+
+    #[test]
+    #[should_panic(expected = "only members can create optimistic proposals")]
+    fn test_optimistic_proposal_not_member() {
+        let (mut ctx, mut contract) = setup_optimistic();
+        add_optimistic(&mut ctx, &mut contract, 3, sample_proposal());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "optimistic proposals support only Transfer, RecurringPayment and GrantAllowance"
+    )]
+    fn test_optimistic_proposal_action() {
+        let (mut ctx, mut contract) = setup_optimistic();
+        let mut p = sample_proposal();
+        p.action = Action::SetMember {
+            member: accounts(3),
+            power: 5,
+        };
+        add_optimistic(&mut ctx, &mut contract, 0, p);
+    }
+
+    #[test]
+    #[should_panic(expected = "optimistic proposal amount must not be bigger than")]
+    fn test_optimistic_proposal_over_cap() {
+        let (mut ctx, mut contract) = setup_optimistic();
+        add_optimistic(
+            &mut ctx,
+            &mut contract,
+            0,
+            transfer_proposal(DEFAULT_TRANSFER + 1),
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "objection threshold must be between 1 and the total members power (9)"
+    )]
+    fn test_objection_threshold_too_big() {
+        let (mut ctx, mut contract) = setup_contract(5);
+        let mut p = sample_proposal();
+        p.action = Action::SetObjectionThreshold {
+            threshold: 10,
+            max_amount: DEFAULT_TRANSFER.into(),
+        };
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 1);
+        contract.add_proposal(p);
+    }
+
+    /// Conviction halves each 10 seconds, requests can ask for up to 50% of the treasury and
+    /// the smallest requests need 1% of the total power staked.
+    fn conviction_params() -> ConvictionParams {
+        ConvictionParams {
+            period: 10,
+            decay: 500_000,
+            max_share: 5000,
+            weight: 10_000,
+        }
+    }
+
+    /// Enables conviction voting and adds a funding request 0 at timestamp 41.
+    fn setup_conviction() -> (VMContextBuilder, Contract) {
+        let (mut ctx, mut contract) = setup_contract(5);
+        pass_and_execute(
+            &mut ctx,
+            &mut contract,
+            Action::SetConviction {
+                params: Some(conviction_params()),
+            },
+        );
+        update_context(&mut ctx, 0, BASE_UNIT * 300, 41);
+        contract.add_funding_request(accounts(3), DEFAULT_TRANSFER.into(), "tooling".into());
+        (ctx, contract)
+    }
+
+    #[test]
+    fn test_conviction_stakes_rescaled() {
+        let (mut ctx, mut contract) = setup_conviction();
+        update_context(&mut ctx, 0, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 2);
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+
+        // charlie's power drops to 2, the stake is halved.
+        set_member_at(&mut ctx, &mut contract, 2, 2);
+        assert_eq!(contract.conviction_stakes(accounts(2)), vec![(0, 2)]);
+        let r = contract.funding_request(0);
+        assert_eq!(r.staked, 4);
+        // the conviction grew with the full stake until the change.
+        assert!(r.conviction.0 > 6 * SCALE);
+
+        // a removed member loses the stakes.
+        set_member_at(&mut ctx, &mut contract, 0, 0);
+        assert!(contract.conviction_stakes(accounts(0)).is_empty());
+        assert_eq!(contract.funding_request(0).staked, 2);
+    }
+
+    #[test]
+    fn test_conviction_stake_while_disabled() {
+        let (mut ctx, mut contract) = setup_conviction();
+        pass_and_execute_at(
+            &mut ctx,
+            &mut contract,
+            Action::SetConviction { params: None },
+            41,
+        );
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 81);
+        contract.stake_conviction(0, 4);
+        pass_and_execute_at(
+            &mut ctx,
+            &mut contract,
+            Action::SetConviction {
+                params: Some(conviction_params()),
+            },
+            81,
+        );
+
+        // the stake counts only since it was made.
+        update_context(&mut ctx, 4, 0, 131);
+        let r = contract.funding_request(0);
+        assert_eq!(r.conviction, conviction_params().grow(0, 4, 5).into());
+    }
+
+    #[test]
+    fn test_conviction_math() {
+        let params = conviction_params();
+        assert_eq!(params.grow(0, 4, 0), 0);
+        assert_eq!(params.grow(0, 4, 1), 4 * SCALE);
+        assert_eq!(params.grow(0, 4, 2), 6 * SCALE);
+        assert_eq!(params.grow(8 * SCALE, 0, 1), 4 * SCALE);
+        assert_eq!(params.grow(8 * SCALE, 4, 100), 8 * SCALE);
+        assert_eq!(params.threshold(0, 100, 9), Some(720_000_000_000));
+        assert_eq!(params.threshold(25, 100, 9), Some(2_880_000_000_000));
+        assert_eq!(params.threshold(50, 100, 9), None);
+        assert_eq!(params.threshold(1, 0, 9), None);
+    }
+
+    #[test]
+    fn test_funding_request() {
+        let (mut ctx, mut contract) = setup_conviction();
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+        assert_eq!(contract.conviction_stakes(accounts(2)), vec![(0, 4)]);
+
+        update_context(&mut ctx, 4, 0, 51);
+        let r = contract.funding_request(0);
+        assert_eq!(r.staked, 4);
+        assert_eq!(r.conviction, (4 * SCALE).into());
+        assert!(r.threshold.unwrap().0 < 4 * SCALE);
+        contract.execute_funding_request(0);
+        assert!(contract.funding_request(0).executed);
+        assert_transferred(DEFAULT_TRANSFER);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough conviction")]
+    fn test_funding_request_not_enough_conviction() {
+        let (mut ctx, mut contract) = setup_conviction();
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+        update_context(&mut ctx, 4, 0, 50);
+        contract.execute_funding_request(0);
+    }
+
+    #[test]
+    fn test_cancel_funding_request() {
+        let (mut ctx, mut contract) = setup_conviction();
+        contract.add_funding_request(accounts(4), DEFAULT_TRANSFER.into(), "audit".into());
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+
+        update_context(&mut ctx, 0, 1, 45);
+        contract.cancel_funding_request(0);
+        assert!(contract.funding_request(0).cancelled);
+        // the stake on the cancelled request is released.
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 46);
+        contract.stake_conviction(1, 4);
+        assert_eq!(contract.conviction_stakes(accounts(2)), vec![(1, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "funding request cancelled")]
+    fn test_execute_cancelled_funding_request() {
+        let (mut ctx, mut contract) = setup_conviction();
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+        update_context(&mut ctx, 0, 1, 45);
+        contract.cancel_funding_request(0);
+        update_context(&mut ctx, 4, 0, 51);
+        contract.execute_funding_request(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "only the proposer (alice) can cancel the funding request")]
+    fn test_cancel_funding_request_not_proposer() {
+        let (mut ctx, mut contract) = setup_conviction();
+        update_context(&mut ctx, 2, 1, 45);
+        contract.cancel_funding_request(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough power to stake (available: 4, required: 5)")]
+    fn test_stake_conviction_over_power() {
+        let (mut ctx, mut contract) = setup_conviction();
+        contract.add_funding_request(accounts(4), DEFAULT_TRANSFER.into(), "audit".into());
+        update_context(&mut ctx, 2, BASE_UNIT * 4, 41);
+        contract.stake_conviction(0, 4);
+        contract.stake_conviction(1, 1);
+    }
+
+    fn vote_alice_and_charile(ctx: &mut VMContextBuilder, contract: &mut Contract) {
+        update_context(ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote(0, true);
+        update_context(ctx, 2, BASE_UNIT * 4, 10); // charile power = 5
+        contract.vote(0, true);
+    }
+
+    fn sample_proposal() -> NewProposal {
+        NewProposal {
+            action: Action::Transfer {
+                dest: accounts(3),
+                amount: DEFAULT_TRANSFER.into(),
+            },
+            description: "transfer to danny".into(),
+            voting_start: 10.into(),
+            voting_duration: 20,
+            execute_before: 100.into(),
+            reveal_duration: None,
+            optimistic: false,
+        }
+    }
+
+    fn transfer_proposal(amount: Balance) -> NewProposal {
+        let mut p = sample_proposal();
+        p.action = Action::Transfer {
+            dest: accounts(3),
+            amount: amount.into(),
+        };
+        p
+    }
+
+    /// Grant for danny: 10'000 yocto, vesting during 100 seconds with 10 seconds cliff.
+    fn grant_action(start: u64, revocable: bool) -> Action {
+        Action::CreateGrant {
+            beneficiary: accounts(3),
+            total: 10_000.into(),
+            start: start.into(),
+            cliff: 10,
+            duration: 100,
+            revocable,
+        }
+    }
+
+    /// Allowance for bob, which expires at 100.
+    fn allowance_action(amount: Balance) -> Action {
+        Action::GrantAllowance {
+            member: accounts(1),
+            amount: amount.into(),
+            expiry: 100.into(),
+        }
+    }
+
+    /// Escrow for danny with two milestones: 1000 and 2000 yocto.
+    fn escrow_action(release_support: u32) -> Action {
+        Action::CreateEscrow {
+            beneficiary: accounts(3),
+            milestones: vec![
+                NewMilestone {
+                    name: "design".into(),
+                    amount: 1000.into(),
+                },
+                NewMilestone {
+                    name: "implementation".into(),
+                    amount: 2000.into(),
+                },
+            ],
+            release_support,
+        }
+    }
+
+    /// Like `pass_and_execute`, with the proposal created at `t`, voted at `t + 9` by all
+    /// members and executed at `t + 39`.
+    fn pass_and_execute_at(
+        ctx: &mut VMContextBuilder,
+        contract: &mut Contract,
+        action: Action,
+        t: u64,
+    ) {
+        let mut p = sample_proposal();
+        p.voting_start = (t + 9).into();
+        p.execute_before = (t + 159).into();
+        p.action = action;
+        update_context(ctx, 0, BASE_UNIT * 300, t);
+        let id = contract.add_proposal(p);
+        for voter in 0..3 {
+            update_context(ctx, voter, BASE_UNIT * 4, t + 9);
+            contract.vote(id, true);
+        }
+        update_context(ctx, 4, 0, t + 39);
+        contract.execute(id);
+    }
+
+    /// Creates a proposal with the given action, passes it and executes it at timestamp 40.
+    fn pass_and_execute(ctx: &mut VMContextBuilder, contract: &mut Contract, action: Action) {
+        update_context(ctx, 0, BASE_UNIT * 300, 1);
+        let mut p = sample_proposal();
+        p.action = action;
+        let id = contract.add_proposal(p);
+        update_context(ctx, 0, BASE_UNIT * 4, 10);
+        contract.vote(id, true);
+        update_context(ctx, 2, BASE_UNIT * 4, 10);
+        contract.vote(id, true);
+        update_context(ctx, 4, 0, 40);
+        contract.execute(id);
     }
 }
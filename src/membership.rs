@@ -0,0 +1,119 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId};
+
+use crate::quadratic::{isqrt, Tally};
+use crate::{events, Contract};
+
+/// Voting policy snapshot. A new version is created with each membership change and
+/// proposals are evaluated against the version from their creation.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Policy {
+    /// minimum support (in power) to pass a proposal
+    pub min_support: u32,
+    /// sum of members power
+    pub total_power: u32,
+    /// Amount of staked tokens per one voting power in the token voting, `None` when the
+    /// power comes from members.
+    pub token_power_unit: Option<U128>,
+    pub tally: Tally,
+}
+
+impl Policy {
+    /// Returns the votes required to pass a proposal. In the quadratic tally `min_support`
+    /// power buys `isqrt(min_support² · credits_per_power)` votes.
+    pub fn min_votes(&self) -> u32 {
+        match self.tally {
+            Tally::Linear => self.min_support,
+            Tally::Quadratic {
+                credits_per_power, ..
+            } => quadratic_votes(self.min_support, credits_per_power),
+        }
+    }
+}
+
+/// Returns the votes bought by spending all voice credits of `power`.
+pub fn quadratic_votes(power: u32, credits_per_power: u32) -> u32 {
+    isqrt(u64::from(power) * u64::from(power) * u64::from(credits_per_power)) as u32
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the policy with the given version.
+    pub fn policy(&self, version: u32) -> Policy {
+        self.policies
+            .get(version.into())
+            .expect("policy version not found")
+    }
+
+    /// Returns the `member` power in the given policy version, 0 if not a member.
+    pub fn member_power(&self, member: ValidAccountId, policy_version: u32) -> u16 {
+        self.power_at(member.as_ref(), policy_version)
+    }
+}
+
+impl Contract {
+    /// Returns the current policy version.
+    pub(crate) fn policy_version(&self) -> u32 {
+        (self.policies.len() - 1) as u32
+    }
+
+    /// Returns the member power in the given policy version, 0 if not a member.
+    pub(crate) fn power_at(&self, member: &AccountId, policy_version: u32) -> u16 {
+        match self.member_history.get(member) {
+            Some(h) => h
+                .iter()
+                .rev()
+                .find(|(since, _)| *since <= policy_version)
+                .map_or(0, |(_, power)| *power),
+            // member didn't change since the first version.
+            None => self.members.get(member).unwrap_or(0),
+        }
+    }
+
+    /// Creates a new policy version from the current one, updated by `f`.
+    pub(crate) fn new_policy_version(&mut self, f: impl FnOnce(&mut Policy)) {
+        let mut p = self.policy(self.policy_version());
+        f(&mut p);
+        self.policies.push(&p);
+    }
+
+    /// Sets the member power, zero power removes the member. Creates a new policy version,
+    /// which applies to proposals created from now on. Conviction stakes of the member are
+    /// fitted into the new power right away and a removed member loses its allowance.
+    pub(crate) fn set_member(&mut self, member: &AccountId, power: u16) {
+        let old = self.members.get(member).unwrap_or(0);
+        assert!(power > 0 || old > 0, "{} is not a member", member);
+        let total_power = self.total_power - u32::from(old) + u32::from(power);
+        assert!(
+            self.min_support <= total_power,
+            "min_support ({}) is bigger than the total members power ({})",
+            self.min_support,
+            total_power
+        );
+        let version = self.policies.len() as u32;
+        let mut history = self
+            .member_history
+            .get(member)
+            .unwrap_or_else(|| vec![(0, old)]);
+        history.push((version, power));
+        self.member_history.insert(member, &history);
+        if power == 0 {
+            self.members.remove(member);
+            self.remove_allowance(member);
+        } else {
+            self.members.insert(member, &power);
+        }
+        self.total_power = total_power;
+        self.new_policy_version(|p| p.total_power = total_power);
+        self.rescale_conviction_stakes(member, power);
+        events::member_changed(member, power);
+    }
+}
@@ -0,0 +1,252 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashSet;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedMap, Vector};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::archive::VoterEntry;
+use crate::membership::Policy;
+use crate::proposal::{ActionInt, Proposal, Vote, Voter};
+use crate::quadratic::Tally;
+use crate::sponsorship::VoteSponsorship;
+use crate::{events, members_map, Contract};
+
+/// Storage key of the contract state version. The key is not present in the v0.2 state.
+pub const STATE_VERSION_KEY: &[u8] = b"VERSION";
+
+/// Version of the contract state layout.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub enum StateVersion {
+    V02,
+    /// v0.3 layout: the borsh serialized `Contract` under the `STATE` key, the state
+    /// version under `VERSION`, stored contract codes under `c` + code hash and the
+    /// `Contract` collections under their one letter prefixes. Any change of this layout
+    /// needs a new variant and a migration from `V03`.
+    V03,
+}
+
+/// Current version of the contract state layout.
+pub const STATE_VERSION: StateVersion = StateVersion::V03;
+
+/// Returns the version of the stored contract state.
+pub fn read_state_version() -> StateVersion {
+    match env::storage_read(STATE_VERSION_KEY) {
+        Some(v) => StateVersion::try_from_slice(&v).expect("invalid state version"),
+        None => StateVersion::V02,
+    }
+}
+
+pub fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}
+
+/// Contract state layout of the v0.2 release.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV02 {
+    pub deployer_id: AccountId,
+    pub members: Vec<Voter>,
+    pub min_support: u32,
+    pub min_duration: u32,
+    pub max_duration: u32,
+    pub min_bond: Balance,
+    pub next_idx: u32,
+    pub proposals: Vector<ProposalV02>,
+}
+
+/// Proposal layout of the v0.2 release.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ProposalV02 {
+    pub proposer: AccountId,
+    pub description: String,
+    pub action: ActionInt,
+    pub voters: HashSet<AccountId>,
+    pub voting_start: u64,
+    pub voting_end: u64,
+    pub votes_for: u32,
+    pub votes_against: u32,
+    pub execute_before: u64,
+    pub executed: bool,
+}
+
+/// Range of proposals stored in the v0.2 layout: proposals with id in `[next, end)` are
+/// not migrated yet.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct ProposalsMigration {
+    pub next: u32,
+    pub end: u32,
+}
+
+impl ProposalsMigration {
+    /// Returns true if the proposal is not migrated yet.
+    pub fn is_pending(&self, proposal_id: u32) -> bool {
+        self.next <= proposal_id && proposal_id < self.end
+    }
+}
+
+impl From<ContractV02> for Contract {
+    fn from(c: ContractV02) -> Contract {
+        let (members, total_power) = members_map(&c.members);
+        let mut policies = Vector::new("y".into());
+        policies.push(&Policy {
+            min_support: c.min_support,
+            total_power,
+            token_power_unit: None,
+            tally: Tally::Linear,
+        });
+        // Proposals keep the v0.2 layout until `migrate_proposals` rewrites them in place,
+        // the vector differs only by the element type.
+        let proposals: Vector<Proposal> =
+            Vector::try_from_slice(&c.proposals.try_to_vec().unwrap()).unwrap();
+        let proposals_migration = ProposalsMigration {
+            next: 0,
+            end: proposals.len() as u32,
+        };
+        Contract {
+            deployer_id: c.deployer_id,
+            members,
+            total_power,
+            min_support: c.min_support,
+            min_duration: c.min_duration,
+            max_duration: c.max_duration,
+            min_bond: c.min_bond,
+            next_idx: c.next_idx,
+            proposals,
+            votes: LookupMap::new("v".into()),
+            vote_index: LookupMap::new("x".into()),
+            commitments: LookupMap::new("j".into()),
+            committed: 0,
+            reserved: 0,
+            payments: Vector::new("r".into()),
+            grants: Vector::new("g".into()),
+            allowances: UnorderedMap::new("a".into()),
+            spends: Vector::new("s".into()),
+            escrows: Vector::new("e".into()),
+            storage_deposits: LookupMap::new("d".into()),
+            storage_deposits_total: 0,
+            vote_sponsorship: VoteSponsorship::default(),
+            sponsored: LookupMap::new("o".into()),
+            delegations: LookupMap::new("l".into()),
+            delegators: LookupMap::new("w".into()),
+            voting_closed_before: 0,
+            policies,
+            member_history: LookupMap::new("h".into()),
+            stake_token: None,
+            stakes: LookupMap::new("t".into()),
+            total_staked: 0,
+            credits: LookupMap::new("q".into()),
+            commits: LookupMap::new("b".into()),
+            vote_keys: LookupMap::new("k".into()),
+            objection_threshold: 0,
+            optimistic_max_amount: 0,
+            conviction_params: None,
+            funding_requests: Vector::new("f".into()),
+            conviction_stakes: LookupMap::new("i".into()),
+            proposals_migration,
+            code_uploaders: LookupMap::new("u".into()),
+            ballots: LookupMap::new("n".into()),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Migrates the stored contract state to the current layout. It must be called by the
+    contract account, right after deploying a new contract code.
+    v0.2 proposals are not migrated by this call, so its cost doesn't depend on the DAO
+    history. They are not available until migrated with `migrate_proposals`.
+    Proposals which passed before the migration don't have funds committed. */
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        assert!(
+            env::predecessor_account_id() == env::current_account_id(),
+            "migrate can be called only by the contract account"
+        );
+        let version = read_state_version();
+        let contract = match version {
+            StateVersion::V02 => {
+                let c: ContractV02 = env::state_read().expect("contract state not found");
+                Contract::from(c)
+            }
+            StateVersion::V03 => env::state_read().expect("contract state not found"),
+        };
+        write_state_version();
+        events::state_migrated(&format!("{:?}", version));
+        contract
+    }
+    /**
+    Migrates up to `limit` v0.2 proposals, together with their votes, to the current layout.
+    v0.2 didn't record vote choices nor the vote power, only voters. A migrated vote gets
+    the voter power from the v0.2 member set (the first policy version), even if a later
+    `SetMember` changed it. Anyone can call this function.
+    Returns the number of proposals left to migrate. */
+    pub fn migrate_proposals(&mut self, limit: u32) -> u32 {
+        let (next, end) = (self.proposals_migration.next, self.proposals_migration.end);
+        assert!(next < end, "no proposals to migrate");
+        let to = end.min(next.saturating_add(limit));
+        let old: Vector<ProposalV02> =
+            Vector::try_from_slice(&self.proposals.try_to_vec().unwrap()).unwrap();
+        for id in next..to {
+            let p = old.get(id.into()).unwrap();
+            let voters = p.voters.len() as u32;
+            // v0.2 voters paid for their votes.
+            for (n, v) in p.voters.into_iter().enumerate() {
+                let vote = Vote {
+                    support: None,
+                    power: self.power_at(&v, 0).into(),
+                    delegated: false,
+                };
+                self.votes.insert(&(id, v.clone()), &vote);
+                self.vote_index.insert(
+                    &(id, n as u32),
+                    &VoterEntry {
+                        voter: v.clone(),
+                        payer: Some(v),
+                    },
+                );
+            }
+            self.proposals.replace(
+                id.into(),
+                &Proposal {
+                    proposer: p.proposer,
+                    description: p.description,
+                    action: p.action,
+                    voting_start: p.voting_start,
+                    voting_end: p.voting_end,
+                    votes_for: p.votes_for,
+                    votes_against: p.votes_against,
+                    execute_before: p.execute_before,
+                    executed: p.executed,
+                    description_hash: None,
+                    policy_version: 0,
+                    reveal_end: None,
+                    objection_threshold: None,
+                    voters,
+                    executed_option: None,
+                },
+            );
+        }
+        self.proposals_migration.next = to;
+        events::proposals_migrated(next, to);
+        end - to
+    }
+}
+
+impl Contract {
+    /// Returns proposal by id.
+    /// Panics when `proposal_id` is not found or the proposal is not migrated yet.
+    pub(crate) fn get_proposal(&self, proposal_id: u32) -> Proposal {
+        assert!(
+            !self.proposals_migration.is_pending(proposal_id),
+            "proposal {} is not migrated yet, use migrate_proposals",
+            proposal_id
+        );
+        self.proposals
+            .get(proposal_id.into())
+            .expect("proposal_id not found")
+    }
+}
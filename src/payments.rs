@@ -0,0 +1,157 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::proposal::FROM_NANO;
+use crate::{events, Contract};
+
+/// Recurring payment schedule created by an executed proposal.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct RecurringPayment {
+    pub recipient: AccountId,
+    /// amount paid in each installment
+    pub amount: Balance,
+    /// Unix timestamp (in seconds) when the first installment is due.
+    pub start: u64,
+    /// Time (in seconds) between installments.
+    pub interval: u32,
+    /// Total number of installments.
+    pub installments: u32,
+    /// Number of installments already claimed by the recipient.
+    pub claimed: u32,
+}
+
+impl RecurringPayment {
+    /// Returns number of installments due at time `t` (in seconds), including the claimed ones.
+    pub fn due(&self, t: u64) -> u32 {
+        if t < self.start {
+            return 0;
+        }
+        let n = (t - self.start) / u64::from(self.interval) + 1;
+        if n >= u64::from(self.installments) {
+            self.installments
+        } else {
+            n as u32
+        }
+    }
+
+    /// Returns the amount which is not claimed yet.
+    pub fn remaining(&self) -> Balance {
+        payment_total(self.amount, self.installments - self.claimed)
+    }
+}
+
+/// Returns the amount paid in `installments` of `amount`.
+/// Panics if the total doesn't fit in `Balance`.
+pub fn payment_total(amount: Balance, installments: u32) -> Balance {
+    amount
+        .checked_mul(Balance::from(installments))
+        .expect("payment total overflows")
+}
+
+/// Returns the number of installments of a schedule starting at `start`, with installments
+/// every `interval` seconds, limited by `count` installments and the `end` timestamp.
+pub fn installments(start: u64, interval: u32, count: Option<u32>, end: Option<u64>) -> u32 {
+    assert!(interval > 0, "payment interval must be positive");
+    let by_end = end.map(|e| {
+        if e < start {
+            0
+        } else {
+            let n = (e - start) / u64::from(interval) + 1;
+            if n > u64::from(u32::MAX) {
+                u32::MAX
+            } else {
+                n as u32
+            }
+        }
+    });
+    match (count, by_end) {
+        (Some(c), Some(e)) => c.min(e),
+        (Some(c), None) => c,
+        (None, Some(e)) => e,
+        (None, None) => panic!("recurring payment must have a count or an end"),
+    }
+}
+
+/// JSON compatible return type for RecurringPayment.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecurringPaymentOut {
+    pub recipient: AccountId,
+    pub amount: U128,
+    pub start: U64,
+    pub interval: u32,
+    pub installments: u32,
+    pub claimed: u32,
+}
+
+impl From<RecurringPayment> for RecurringPaymentOut {
+    fn from(p: RecurringPayment) -> RecurringPaymentOut {
+        RecurringPaymentOut {
+            recipient: p.recipient,
+            amount: p.amount.into(),
+            start: p.start.into(),
+            interval: p.interval,
+            installments: p.installments,
+            claimed: p.claimed,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Transfers all due and not yet claimed installments of a recurring payment.
+    /// Can be called only by the payment recipient.
+    pub fn claim_payment(&mut self, payment_id: u32) -> Promise {
+        let idx: u64 = payment_id.into();
+        let mut p = self.payments.get(idx).expect("payment_id not found");
+        assert!(
+            env::predecessor_account_id() == p.recipient,
+            "only the recipient can claim the payment"
+        );
+        let due = p.due(env::block_timestamp() / FROM_NANO);
+        assert!(due > p.claimed, "no installment is due");
+        let amount = p.amount * Balance::from(due - p.claimed);
+        p.claimed = due;
+        self.payments.replace(idx, &p);
+        self.reserved -= amount;
+        events::treasury("payment_claimed", Some(payment_id), &p.recipient, amount);
+        Promise::new(p.recipient).transfer(amount)
+    }
+
+    /// Returns recurring payment by id.
+    /// Panics when `payment_id` is not found.
+    pub fn payment(&self, payment_id: u32) -> RecurringPaymentOut {
+        self.payments
+            .get(payment_id.into())
+            .expect("payment_id not found")
+            .into()
+    }
+}
+
+impl Contract {
+    /// Creates a new recurring payment and reserves funds for all its installments.
+    pub(crate) fn create_payment(&mut self, p: RecurringPayment) {
+        self.reserved += p.remaining();
+        self.payments.push(&p);
+        let id = (self.payments.len() - 1) as u32;
+        events::treasury("payment_created", Some(id), &p.recipient, p.remaining());
+    }
+
+    /// Cancels all installments which are not due yet and releases their funds.
+    pub(crate) fn cancel_payment(&mut self, payment_id: u32) {
+        let idx: u64 = payment_id.into();
+        let mut p = self.payments.get(idx).expect("payment_id not found");
+        let remaining = p.remaining();
+        p.installments = p.due(env::block_timestamp() / FROM_NANO).max(p.claimed);
+        self.reserved -= remaining - p.remaining();
+        self.payments.replace(idx, &p);
+        events::treasury("payment_cancelled", Some(payment_id), &p.recipient, 0);
+    }
+}
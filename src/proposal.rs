@@ -2,13 +2,20 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use std::collections::HashSet;
 use std::convert::TryInto;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, AccountId, Balance, Promise};
+use near_sdk::{env, AccountId, Balance, PublicKey};
+
+use crate::choice::{assert_valid_options, winner, Ballot, ChoiceMethod};
+use crate::config::MAX_POWER;
+use crate::conviction::ConvictionParams;
+use crate::escrow::{assert_valid_escrow, NewMilestone};
+use crate::payments::{installments, payment_total};
+use crate::quadratic::Tally;
+use crate::vesting::assert_valid_grant;
 
 pub(crate) const FROM_NANO: u64 = 1_000_000_000;
 
@@ -23,8 +30,187 @@ pub struct Voter {
 /// Internal Action representation
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum ActionInt {
-    Transfer { dest: AccountId, amount: Balance },
-    Delete { dest: AccountId },
+    Transfer {
+        dest: AccountId,
+        amount: Balance,
+    },
+    Delete {
+        dest: AccountId,
+    },
+    RecurringPayment {
+        recipient: AccountId,
+        amount: Balance,
+        start: u64,
+        interval: u32,
+        count: Option<u32>,
+        end: Option<u64>,
+    },
+    CancelPayment {
+        payment_id: u32,
+    },
+    CreateGrant {
+        beneficiary: AccountId,
+        total: Balance,
+        start: u64,
+        cliff: u32,
+        duration: u32,
+        revocable: bool,
+    },
+    RevokeGrant {
+        grant_id: u32,
+    },
+    GrantAllowance {
+        member: AccountId,
+        amount: Balance,
+        expiry: u64,
+    },
+    CreateEscrow {
+        beneficiary: AccountId,
+        /// milestone names and amounts
+        milestones: Vec<(String, Balance)>,
+        release_support: u32,
+    },
+    CancelEscrow {
+        escrow_id: u32,
+    },
+    Upgrade {
+        code_hash: Vec<u8>,
+    },
+    AddFullAccessKey {
+        public_key: PublicKey,
+    },
+    AddFunctionCallKey {
+        public_key: PublicKey,
+        allowance: Balance,
+        receiver_id: AccountId,
+        method_names: Vec<String>,
+    },
+    DeleteKey {
+        public_key: PublicKey,
+    },
+    CreateSubAccount {
+        name: String,
+        amount: Balance,
+        public_key: Option<PublicKey>,
+        code_hash: Option<Vec<u8>>,
+    },
+    SetVoteSponsorship {
+        cap: Balance,
+        period: u32,
+    },
+    SetObjectionThreshold {
+        threshold: u32,
+        max_amount: Balance,
+    },
+    SetConviction {
+        params: Option<ConvictionParams>,
+    },
+    SetMember {
+        member: AccountId,
+        power: u16,
+    },
+    SetTokenVoting {
+        token: AccountId,
+        power_unit: Balance,
+    },
+    SetTally {
+        tally: Tally,
+    },
+    Choice {
+        options: Vec<ActionInt>,
+        method: ChoiceMethod,
+    },
+}
+
+impl ActionInt {
+    /// Panics if the action parameters are not valid.
+    pub fn validate(&self) {
+        match self {
+            ActionInt::RecurringPayment {
+                amount,
+                start,
+                interval,
+                count,
+                end,
+                ..
+            } => {
+                let n = installments(*start, *interval, *count, *end);
+                assert!(
+                    n > 0,
+                    "recurring payment must have at least one installment"
+                );
+                payment_total(*amount, n);
+            }
+            ActionInt::CreateGrant {
+                cliff, duration, ..
+            } => assert_valid_grant(*cliff, *duration),
+            ActionInt::CreateEscrow {
+                milestones,
+                release_support,
+                ..
+            } => assert_valid_escrow(milestones, *release_support),
+            ActionInt::Upgrade { code_hash } => {
+                assert!(code_hash.len() == 32, "code_hash must be a sha256 hash")
+            }
+            ActionInt::AddFunctionCallKey { method_names, .. } => {
+                for m in method_names {
+                    assert!(
+                        !m.is_empty() && !m.contains(','),
+                        "method name {:?} is not valid",
+                        m
+                    );
+                }
+            }
+            ActionInt::SetVoteSponsorship { period, .. } => {
+                assert!(*period > 0, "sponsorship period must be positive")
+            }
+            ActionInt::SetTally { tally } => tally.validate(),
+            ActionInt::SetConviction { params: Some(p) } => p.validate(),
+            ActionInt::Choice { options, .. } => assert_valid_options(options),
+            ActionInt::SetMember { power, .. } => assert!(
+                *power <= MAX_POWER,
+                "member power must not be bigger than {}",
+                MAX_POWER
+            ),
+            _ => (),
+        }
+    }
+
+    /// Returns the amount of NEAR (in yocto) the action transfers out of the contract.
+    pub fn amount(&self) -> Balance {
+        match self {
+            ActionInt::Transfer { amount, .. } => *amount,
+            ActionInt::Delete { .. } => 0,
+            ActionInt::RecurringPayment {
+                amount,
+                start,
+                interval,
+                count,
+                end,
+                ..
+            } => payment_total(*amount, installments(*start, *interval, *count, *end)),
+            ActionInt::CancelPayment { .. } => 0,
+            ActionInt::CreateGrant { total, .. } => *total,
+            ActionInt::RevokeGrant { .. } => 0,
+            ActionInt::GrantAllowance { amount, .. } => *amount,
+            ActionInt::CreateEscrow { milestones, .. } => milestones.iter().map(|m| m.1).sum(),
+            ActionInt::CancelEscrow { .. } => 0,
+            ActionInt::Upgrade { .. } => 0,
+            ActionInt::AddFullAccessKey { .. } => 0,
+            ActionInt::AddFunctionCallKey { .. } => 0,
+            ActionInt::DeleteKey { .. } => 0,
+            ActionInt::CreateSubAccount { amount, .. } => *amount,
+            ActionInt::SetVoteSponsorship { .. } => 0,
+            ActionInt::SetObjectionThreshold { .. } => 0,
+            ActionInt::SetConviction { .. } => 0,
+            ActionInt::SetMember { .. } => 0,
+            ActionInt::SetTokenVoting { .. } => 0,
+            ActionInt::SetTally { .. } => 0,
+            ActionInt::Choice { options, .. } => {
+                options.iter().map(ActionInt::amount).max().unwrap_or(0)
+            }
+        }
+    }
 }
 
 /// Action is a JSON compatible type for encodidng actions
@@ -32,8 +218,138 @@ pub enum ActionInt {
 #[cfg_attr(feature = "test", derive(Clone, Debug, PartialEq))]
 #[serde(crate = "near_sdk::serde")]
 pub enum Action {
-    Transfer { dest: ValidAccountId, amount: U128 },
-    Delete { dest: ValidAccountId },
+    Transfer {
+        dest: ValidAccountId,
+        amount: U128,
+    },
+    /// Deletes the contract account and sends its balance to `dest`. Fails while tokens are
+    /// staked or storage balances exist.
+    Delete {
+        dest: ValidAccountId,
+    },
+    /// Creates a recurring payment schedule. The first installment is due at `start` (unix
+    /// timestamp in seconds) and each next one `interval` seconds later. The schedule ends
+    /// after `count` installments or at the `end` timestamp, whichever comes first.
+    /// At least one of `count` and `end` must be set.
+    RecurringPayment {
+        recipient: ValidAccountId,
+        amount: U128,
+        start: U64,
+        interval: u32,
+        count: Option<u32>,
+        end: Option<U64>,
+    },
+    /// Cancels a recurring payment. Installments which are already due can still be claimed.
+    CancelPayment {
+        payment_id: u32,
+    },
+    /// Creates a vesting grant. Nothing is vested before `start + cliff` (unix timestamp in
+    /// seconds), then the `total` vests linearly until `start + duration`.
+    CreateGrant {
+        beneficiary: ValidAccountId,
+        total: U128,
+        start: U64,
+        cliff: u32,
+        duration: u32,
+        revocable: bool,
+    },
+    /// Revokes a revocable grant: stops vesting and releases the unvested funds. Vested
+    /// funds can still be withdrawn by the beneficiary.
+    RevokeGrant {
+        grant_id: u32,
+    },
+    /// Grants a member an allowance which the member can spend without a vote, until the
+    /// `expiry` (unix timestamp in seconds). Replaces the previous allowance of the member,
+    /// so zero `amount` revokes it.
+    GrantAllowance {
+        member: ValidAccountId,
+        amount: U128,
+        expiry: U64,
+    },
+    /// Locks funds for the `beneficiary`. Each milestone is released once confirmed by
+    /// members with `release_support` power, which must not be bigger than the contract
+    /// `min_support`.
+    CreateEscrow {
+        beneficiary: ValidAccountId,
+        milestones: Vec<NewMilestone>,
+        release_support: u32,
+    },
+    /// Cancels an escrow. Funds of not released milestones return to the treasury.
+    CancelEscrow {
+        escrow_id: u32,
+    },
+    /// Deploys a contract code, stored with `store_code`, and migrates the contract state.
+    Upgrade {
+        code_hash: Base64VecU8,
+    },
+    /// Adds a full access key to the contract account.
+    AddFullAccessKey {
+        public_key: Base58PublicKey,
+    },
+    /// Adds a function call access key to the contract account. The key can call only
+    /// `method_names` (all methods if empty) of the `receiver_id` contract and spend at most
+    /// `allowance` on gas (zero allowance means unlimited).
+    AddFunctionCallKey {
+        public_key: Base58PublicKey,
+        allowance: U128,
+        receiver_id: ValidAccountId,
+        method_names: Vec<String>,
+    },
+    /// Deletes an access key of the contract account.
+    DeleteKey {
+        public_key: Base58PublicKey,
+    },
+    /// Creates `<name>.<dao account>` sub-account, funded with `amount`. Optionally adds
+    /// a full access key to the new account and deploys a code stored with `store_code`.
+    CreateSubAccount {
+        name: String,
+        amount: U128,
+        public_key: Option<Base58PublicKey>,
+        code_hash: Option<Base64VecU8>,
+    },
+    /// Pays the members vote storage from the contract balance, up to `cap` (in yocto NEAR)
+    /// per member in each `period` (in seconds). Zero `cap` disables the sponsorship.
+    SetVoteSponsorship {
+        cap: U128,
+        period: u32,
+    },
+    /// Sets the objection power which rejects optimistic proposals, between 1 and the total
+    /// members power, and the maximum amount an optimistic proposal can transfer. Zero
+    /// `max_amount` disables optimistic proposals. The change applies to proposals created
+    /// after the execution.
+    SetObjectionThreshold {
+        threshold: u32,
+        max_amount: U128,
+    },
+    /// Sets the conviction voting parameters of funding requests. `None` disables conviction
+    /// voting: funding requests can't be added nor executed.
+    SetConviction {
+        params: Option<ConvictionParams>,
+    },
+    /// Adds a member, changes the member power or removes the member (zero `power`).
+    /// The change applies to proposals created after the execution.
+    SetMember {
+        member: ValidAccountId,
+        power: u16,
+    },
+    /// Switches to the token weighted voting: voting power is the amount of `token` staked
+    /// by the voter divided by `power_unit`. Zero `power_unit` switches back to the members
+    /// voting. The change applies to proposals created after the execution.
+    SetTokenVoting {
+        token: ValidAccountId,
+        power_unit: U128,
+    },
+    /// Sets the strategy of counting votes. The change applies to proposals created after
+    /// the execution.
+    SetTally {
+        tally: Tally,
+    },
+    /// Multi-option proposal: members vote for the options with `vote_option` and only the
+    /// winning option is executed. Options can't be multi-option actions.
+    Choice {
+        options: Vec<Action>,
+        method: ChoiceMethod,
+    },
 }
 
 impl Action {
@@ -47,6 +363,126 @@ impl Action {
             Action::Delete { dest } => ActionInt::Delete {
                 dest: dest.clone().into(),
             },
+            Action::RecurringPayment {
+                recipient,
+                amount,
+                start,
+                interval,
+                count,
+                end,
+            } => ActionInt::RecurringPayment {
+                recipient: recipient.clone().into(),
+                amount: amount.clone().into(),
+                start: start.clone().into(),
+                interval: *interval,
+                count: *count,
+                end: end.clone().map(u64::from),
+            },
+            Action::CancelPayment { payment_id } => ActionInt::CancelPayment {
+                payment_id: *payment_id,
+            },
+            Action::CreateGrant {
+                beneficiary,
+                total,
+                start,
+                cliff,
+                duration,
+                revocable,
+            } => ActionInt::CreateGrant {
+                beneficiary: beneficiary.clone().into(),
+                total: total.clone().into(),
+                start: start.clone().into(),
+                cliff: *cliff,
+                duration: *duration,
+                revocable: *revocable,
+            },
+            Action::RevokeGrant { grant_id } => ActionInt::RevokeGrant {
+                grant_id: *grant_id,
+            },
+            Action::GrantAllowance {
+                member,
+                amount,
+                expiry,
+            } => ActionInt::GrantAllowance {
+                member: member.clone().into(),
+                amount: amount.clone().into(),
+                expiry: expiry.clone().into(),
+            },
+            Action::CreateEscrow {
+                beneficiary,
+                milestones,
+                release_support,
+            } => ActionInt::CreateEscrow {
+                beneficiary: beneficiary.clone().into(),
+                milestones: milestones
+                    .iter()
+                    .map(|m| (m.name.clone(), m.amount.clone().into()))
+                    .collect(),
+                release_support: *release_support,
+            },
+            Action::CancelEscrow { escrow_id } => ActionInt::CancelEscrow {
+                escrow_id: *escrow_id,
+            },
+            Action::Upgrade { code_hash } => ActionInt::Upgrade {
+                code_hash: code_hash.clone().into(),
+            },
+            Action::AddFullAccessKey { public_key } => ActionInt::AddFullAccessKey {
+                public_key: public_key.0.clone(),
+            },
+            Action::AddFunctionCallKey {
+                public_key,
+                allowance,
+                receiver_id,
+                method_names,
+            } => ActionInt::AddFunctionCallKey {
+                public_key: public_key.0.clone(),
+                allowance: allowance.clone().into(),
+                receiver_id: receiver_id.clone().into(),
+                method_names: method_names.clone(),
+            },
+            Action::DeleteKey { public_key } => ActionInt::DeleteKey {
+                public_key: public_key.0.clone(),
+            },
+            Action::CreateSubAccount {
+                name,
+                amount,
+                public_key,
+                code_hash,
+            } => ActionInt::CreateSubAccount {
+                name: name.clone(),
+                amount: amount.clone().into(),
+                public_key: public_key.as_ref().map(|k| k.0.clone()),
+                code_hash: code_hash.clone().map(Vec::from),
+            },
+            Action::SetVoteSponsorship { cap, period } => ActionInt::SetVoteSponsorship {
+                cap: cap.clone().into(),
+                period: *period,
+            },
+            Action::SetObjectionThreshold {
+                threshold,
+                max_amount,
+            } => ActionInt::SetObjectionThreshold {
+                threshold: *threshold,
+                max_amount: max_amount.clone().into(),
+            },
+            Action::SetConviction { params } => ActionInt::SetConviction {
+                params: params.clone(),
+            },
+            Action::SetMember { member, power } => ActionInt::SetMember {
+                member: member.clone().into(),
+                power: *power,
+            },
+            Action::SetTokenVoting { token, power_unit } => ActionInt::SetTokenVoting {
+                token: token.clone().into(),
+                power_unit: power_unit.clone().into(),
+            },
+            Action::SetTally { tally } => ActionInt::SetTally {
+                tally: tally.clone(),
+            },
+            Action::Choice { options, method } => ActionInt::Choice {
+                options: options.iter().map(Action::to_aint).collect(),
+                method: *method,
+            },
         }
     }
 }
@@ -61,6 +497,119 @@ impl Into<Action> for ActionInt {
             ActionInt::Delete { dest } => Action::Delete {
                 dest: dest.try_into().unwrap(),
             },
+            ActionInt::RecurringPayment {
+                recipient,
+                amount,
+                start,
+                interval,
+                count,
+                end,
+            } => Action::RecurringPayment {
+                recipient: recipient.try_into().unwrap(),
+                amount: amount.into(),
+                start: start.into(),
+                interval,
+                count,
+                end: end.map(U64::from),
+            },
+            ActionInt::CancelPayment { payment_id } => Action::CancelPayment { payment_id },
+            ActionInt::CreateGrant {
+                beneficiary,
+                total,
+                start,
+                cliff,
+                duration,
+                revocable,
+            } => Action::CreateGrant {
+                beneficiary: beneficiary.try_into().unwrap(),
+                total: total.into(),
+                start: start.into(),
+                cliff,
+                duration,
+                revocable,
+            },
+            ActionInt::RevokeGrant { grant_id } => Action::RevokeGrant { grant_id },
+            ActionInt::GrantAllowance {
+                member,
+                amount,
+                expiry,
+            } => Action::GrantAllowance {
+                member: member.try_into().unwrap(),
+                amount: amount.into(),
+                expiry: expiry.into(),
+            },
+            ActionInt::CreateEscrow {
+                beneficiary,
+                milestones,
+                release_support,
+            } => Action::CreateEscrow {
+                beneficiary: beneficiary.try_into().unwrap(),
+                milestones: milestones
+                    .into_iter()
+                    .map(|(name, amount)| NewMilestone {
+                        name,
+                        amount: amount.into(),
+                    })
+                    .collect(),
+                release_support,
+            },
+            ActionInt::CancelEscrow { escrow_id } => Action::CancelEscrow { escrow_id },
+            ActionInt::Upgrade { code_hash } => Action::Upgrade {
+                code_hash: code_hash.into(),
+            },
+            ActionInt::AddFullAccessKey { public_key } => Action::AddFullAccessKey {
+                public_key: Base58PublicKey(public_key),
+            },
+            ActionInt::AddFunctionCallKey {
+                public_key,
+                allowance,
+                receiver_id,
+                method_names,
+            } => Action::AddFunctionCallKey {
+                public_key: Base58PublicKey(public_key),
+                allowance: allowance.into(),
+                receiver_id: receiver_id.try_into().unwrap(),
+                method_names,
+            },
+            ActionInt::DeleteKey { public_key } => Action::DeleteKey {
+                public_key: Base58PublicKey(public_key),
+            },
+            ActionInt::CreateSubAccount {
+                name,
+                amount,
+                public_key,
+                code_hash,
+            } => Action::CreateSubAccount {
+                name,
+                amount: amount.into(),
+                public_key: public_key.map(Base58PublicKey),
+                code_hash: code_hash.map(Base64VecU8::from),
+            },
+            ActionInt::SetVoteSponsorship { cap, period } => Action::SetVoteSponsorship {
+                cap: cap.into(),
+                period,
+            },
+            ActionInt::SetObjectionThreshold {
+                threshold,
+                max_amount,
+            } => Action::SetObjectionThreshold {
+                threshold,
+                max_amount: max_amount.into(),
+            },
+            ActionInt::SetConviction { params } => Action::SetConviction { params },
+            ActionInt::SetMember { member, power } => Action::SetMember {
+                member: member.try_into().unwrap(),
+                power,
+            },
+            ActionInt::SetTokenVoting { token, power_unit } => Action::SetTokenVoting {
+                token: token.try_into().unwrap(),
+                power_unit: power_unit.into(),
+            },
+            ActionInt::SetTally { tally } => Action::SetTally { tally },
+            ActionInt::Choice { options, method } => Action::Choice {
+                options: options.into_iter().map(ActionInt::into).collect(),
+                method,
+            },
         }
     }
 }
@@ -70,7 +619,6 @@ pub struct Proposal {
     pub proposer: AccountId,
     pub description: String,
     pub action: ActionInt,
-    pub voters: HashSet<AccountId>,
     /// Block timestamp in seconds when voting starts.
     pub voting_start: u64,
     /// Block timestamp in seconds when voting ends.
@@ -79,55 +627,186 @@ pub struct Proposal {
     pub votes_against: u32,
     pub execute_before: u64,
     pub executed: bool,
+    /// sha256 hash of the description, set when the proposal is archived.
+    pub description_hash: Option<Vec<u8>>,
+    /// Policy (members and their power, min_support) version at the proposal creation.
+    /// Votes and the execution are evaluated against it.
+    pub policy_version: u32,
+    /// Block timestamp in seconds when the reveal phase of a secret ballot ends. `None`
+    /// for public votes. Secret votes are committed during voting and revealed after it.
+    pub reveal_end: Option<u64>,
+    /// Set for optimistic proposals: the proposal passes unless the power voting against it
+    /// reaches the objection threshold.
+    pub objection_threshold: Option<u32>,
+    /// Number of accounts with a vote (or a vote commit) in the `Contract::vote_index`.
+    pub voters: u32,
+    /// Index of the executed option of a multi-option proposal.
+    pub executed_option: Option<u8>,
+}
+
+/// Vote cast for a proposal. Votes are stored outside of `Proposal`, so the cost of voting
+/// doesn't grow with the number of voters.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Vote {
+    /// Vote choice. `None` for multi-option votes and for votes cast before v0.3, which
+    /// didn't record the choice.
+    pub support: Option<bool>,
+    pub power: u32,
+    /// true if the vote was cast by the voter delegate.
+    pub delegated: bool,
 }
 
 impl Proposal {
-    pub fn vote(&mut self, voter: &Voter, vote_yes: bool) {
+    /// Adds the voter power to the tally and returns the vote record.
+    /// The caller must check that the voter didn't vote before.
+    pub fn vote(&mut self, power: u32, vote_yes: bool) -> Vote {
         let t: u64 = env::block_timestamp() / FROM_NANO;
         assert!(
             self.voting_start <= t && self.voting_end >= t,
             "voting is not active"
         );
+        self.count(power, vote_yes);
+        Vote {
+            support: Some(vote_yes),
+            power,
+            delegated: false,
+        }
+    }
+
+    /// Adds power of a member, who delegated to the voter, to the tally and returns the
+    /// delegated vote record.
+    pub fn vote_delegated(&mut self, power: u32, vote_yes: bool) -> Vote {
+        self.count(power, vote_yes);
+        Vote {
+            support: Some(vote_yes),
+            power,
+            delegated: true,
+        }
+    }
+
+    /// Removes a delegated vote from the tally, so the member can vote directly.
+    pub fn remove_vote(&mut self, v: &Vote) {
+        match v.support {
+            Some(true) => self.votes_for -= v.power,
+            Some(false) => self.votes_against -= v.power,
+            None => (),
+        }
+    }
+
+    /// Adds the voter power to the turnout of a multi-option proposal. The voter ranking is
+    /// stored by the caller, who must check that the voter didn't vote before.
+    pub fn vote_option(&mut self, power: u32) {
+        let t: u64 = env::block_timestamp() / FROM_NANO;
+        assert!(
+            self.voting_start <= t && self.voting_end >= t,
+            "voting is not active"
+        );
+        self.votes_for += power;
+    }
+
+    /// Adds a revealed secret vote to the tally and returns the vote record.
+    /// The caller must check the vote commitment.
+    pub fn reveal(&mut self, power: u32, vote_yes: bool) -> Vote {
+        let t: u64 = env::block_timestamp() / FROM_NANO;
         assert!(
-            self.voters.insert(voter.account.clone()),
-            "you already voted"
+            self.voting_end < t && t <= self.tally_end(),
+            "reveal is not active"
         );
-        let p: u32 = voter.power.into();
+        self.count(power, vote_yes);
+        Vote {
+            support: Some(vote_yes),
+            power,
+            delegated: false,
+        }
+    }
+
+    /// Returns the block timestamp in seconds when the tally is final: the end of the reveal
+    /// phase for secret ballots, the voting end otherwise.
+    pub fn tally_end(&self) -> u64 {
+        self.reveal_end.unwrap_or(self.voting_end)
+    }
+
+    fn count(&mut self, power: u32, vote_yes: bool) {
         if vote_yes {
-            self.votes_for += p;
+            self.votes_for += power;
         } else {
-            self.votes_against += p;
+            self.votes_against += power;
         }
     }
 
-    pub fn execute(&mut self, min_support: u32) -> Promise {
+    /// Returns true if the proposal is not executed and the current tally is enough to pass.
+    /// `ballots` are the proposal ballots, empty if it's not a multi-option proposal.
+    pub fn is_passing(&self, min_support: u32, ballots: &[Ballot]) -> bool {
+        !self.executed && self.winning_action(min_support, ballots).is_some()
+    }
+
+    /// Returns the action to execute according to the current tally: the winning (or
+    /// executed) option of a multi-option proposal or the proposal action if it has enough
+    /// support (or, for optimistic proposals, not enough objections).
+    pub fn winning_action(&self, min_support: u32, ballots: &[Ballot]) -> Option<&ActionInt> {
+        match (&self.action, self.objection_threshold) {
+            (ActionInt::Choice { options, method }, _) => self
+                .executed_option
+                .map(usize::from)
+                .or_else(|| winner(*method, options.len(), ballots, min_support))
+                .map(|i| &options[i]),
+            (a, Some(threshold)) if self.votes_against < threshold => Some(a),
+            (a, None) if self.votes_for >= min_support && self.votes_for > self.votes_against => {
+                Some(a)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks if the proposal can be executed and marks it as executed.
+    pub fn execute(&mut self, min_support: u32, ballots: &[Ballot]) {
         let t: u64 = env::block_timestamp() / FROM_NANO;
         assert!(
-            self.voting_end < t && t <= self.execute_before,
+            self.tally_end() < t && t <= self.execute_before,
             "proposal can be executed only between {} and {} timestamp [seconds]",
-            self.voting_end + 1,
+            self.tally_end() + 1,
             self.execute_before
         );
-        assert!(
-            self.votes_for >= min_support,
-            "proposal didn't get enough support (got {}, required: {})",
-            self.votes_for,
-            min_support
-        );
-        assert!(
-            self.votes_for > self.votes_against,
-            "proposal didn't pass (votes_for: {}, votes_against: {})",
-            self.votes_for,
-            self.votes_against
-        );
+        if let ActionInt::Choice { options, method } = &self.action {
+            let option = winner(*method, options.len(), ballots, min_support);
+            assert!(
+                option.is_some(),
+                "no option reached the required support ({})",
+                min_support
+            );
+            self.executed_option = option.map(|i| i as u8);
+        }
+        if let Some(threshold) = self.objection_threshold {
+            // optimistic proposals pass unless objected.
+            assert!(
+                self.votes_against < threshold,
+                "proposal was objected (votes_against: {}, objection_threshold: {})",
+                self.votes_against,
+                threshold
+            );
+        } else {
+            assert!(
+                self.votes_for >= min_support,
+                "proposal didn't get enough support (got {}, required: {})",
+                self.votes_for,
+                min_support
+            );
+            assert!(
+                self.votes_for > self.votes_against,
+                "proposal didn't pass (votes_for: {}, votes_against: {})",
+                self.votes_for,
+                self.votes_against
+            );
+        }
         assert!(!self.executed, "proposal already executed");
         self.executed = true;
-        match &self.action {
-            ActionInt::Transfer { dest, amount } => Promise::new(dest.clone()).transfer(*amount),
-            ActionInt::Delete { dest } => {
-                Promise::new(env::current_account_id()).delete_account(dest.clone())
-            }
-        }
+    }
+
+    /// Returns true if the proposal is executed or can't be executed any more.
+    pub fn is_finalized(&self) -> bool {
+        self.executed || env::block_timestamp() / FROM_NANO > self.execute_before
     }
 }
 
@@ -145,13 +824,32 @@ pub struct NewProposal {
     /// `Contract.min_duration` and `Contract.max_duration`.
     pub voting_duration: u32,
     /// Last block timestamp (in seconds) when the proposal can be executed.
-    /// Must be bigger than `voting_start + voting_duration`.
+    /// Must be bigger than `voting_start + voting_duration` (plus `reveal_duration` for
+    /// secret ballots).
     pub execute_before: U64,
+    /// Reveal phase duration in seconds. When set, the proposal uses secret ballots: votes
+    /// are committed with `commit_vote` during the voting and revealed with `reveal_vote`
+    /// afterwards. Unrevealed votes are not counted.
+    pub reveal_duration: Option<u32>,
+    /// Optimistic proposal passes at the voting end unless the power voting against it
+    /// reaches `Contract.objection_threshold`. Only members can create optimistic proposals
+    /// and only with `Transfer`, `RecurringPayment` or `GrantAllowance` actions of at most
+    /// `Contract.optimistic_max_amount`.
+    #[serde(default)]
+    pub optimistic: bool,
 }
 
 impl NewProposal {
-    /// `min_duration` and `max_duration` is expressed in seconds.
-    pub fn into_proposal(&self, min_duration: u32, max_duration: u32) -> Proposal {
+    /// `min_duration` and `max_duration` is expressed in seconds. Zero `objection_threshold`
+    /// or `optimistic_max_amount` disables optimistic proposals.
+    pub fn into_proposal(
+        &self,
+        min_duration: u32,
+        max_duration: u32,
+        policy_version: u32,
+        objection_threshold: u32,
+        optimistic_max_amount: Balance,
+    ) -> Proposal {
         let voting_start = u64::from(self.voting_start);
         let execute_before = u64::from(self.execute_before);
         let t: u64 = env::block_timestamp() / FROM_NANO;
@@ -171,17 +869,64 @@ impl NewProposal {
             execute_before > voting_end,
             "execute_before must be after voting end"
         );
+        let action = self.action.to_aint();
+        action.validate();
+        let reveal_end = self.reveal_duration.map(|d| {
+            assert!(d > 0, "reveal_duration must be positive");
+            assert!(
+                !matches!(action, ActionInt::Choice { .. }),
+                "multi-option proposals can't use secret ballots"
+            );
+            let reveal_end = voting_end + u64::from(d);
+            assert!(
+                execute_before > reveal_end,
+                "execute_before must be after reveal end"
+            );
+            reveal_end
+        });
+        let objection_threshold = if self.optimistic {
+            assert!(
+                objection_threshold > 0 && optimistic_max_amount > 0,
+                "optimistic proposals are disabled"
+            );
+            assert!(
+                !matches!(action, ActionInt::Choice { .. }),
+                "multi-option proposals can't be optimistic"
+            );
+            assert!(
+                matches!(
+                    action,
+                    ActionInt::Transfer { .. }
+                        | ActionInt::RecurringPayment { .. }
+                        | ActionInt::GrantAllowance { .. }
+                ),
+                "optimistic proposals support only Transfer, RecurringPayment and GrantAllowance"
+            );
+            assert!(
+                action.amount() <= optimistic_max_amount,
+                "optimistic proposal amount must not be bigger than {}",
+                optimistic_max_amount
+            );
+            Some(objection_threshold)
+        } else {
+            None
+        };
         return Proposal {
             proposer: env::predecessor_account_id(),
             description: self.description.clone(),
-            action: self.action.to_aint(),
-            voters: HashSet::new(),
+            action,
             voting_start,
             voting_end,
             votes_for: 0,
             votes_against: 0,
             execute_before,
             executed: false,
+            description_hash: None,
+            policy_version,
+            reveal_end,
+            objection_threshold,
+            voters: 0,
+            executed_option: None,
         };
     }
 }
@@ -201,6 +946,16 @@ pub struct ProposalOut {
     pub votes_against: u32,
     pub execute_before: U64,
     pub executed: bool,
+    /// sha256 hash of the description of an archived proposal. Description of an archived
+    /// proposal is empty.
+    pub description_hash: Option<Base64VecU8>,
+    pub policy_version: u32,
+    /// End of the reveal phase of a secret ballot.
+    pub reveal_end: Option<U64>,
+    /// Objection threshold of an optimistic proposal.
+    pub objection_threshold: Option<u32>,
+    /// Index of the executed option of a multi-option proposal.
+    pub executed_option: Option<u8>,
 }
 
 impl From<Proposal> for ProposalOut {
@@ -214,6 +969,11 @@ impl From<Proposal> for ProposalOut {
             votes_against: p.votes_against,
             execute_before: p.execute_before.into(),
             executed: p.executed,
+            description_hash: p.description_hash.map(Base64VecU8::from),
+            policy_version: p.policy_version,
+            reveal_end: p.reveal_end.map(U64::from),
+            objection_threshold: p.objection_threshold,
+            executed_option: p.executed_option,
         }
     }
 }
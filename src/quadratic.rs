@@ -0,0 +1,131 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::proposal::FROM_NANO;
+use crate::Contract;
+
+/// Strategy of counting votes.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum Tally {
+    /// A vote counts with the voter power.
+    Linear,
+    /// Each voter gets `credits_per_power` voice credits per unit of power in each `epoch`
+    /// (in seconds) and allocates them across proposals with `vote_with_credits`.
+    /// A vote counts with the square root of the credits spent on it.
+    Quadratic { credits_per_power: u32, epoch: u32 },
+}
+
+impl Tally {
+    /// Panics if the tally parameters are not valid.
+    pub fn validate(&self) {
+        if let Tally::Quadratic {
+            credits_per_power,
+            epoch,
+        } = self
+        {
+            assert!(
+                *credits_per_power > 0 && *epoch > 0,
+                "credits_per_power and epoch must be positive"
+            );
+        }
+    }
+}
+
+/// Voice credits spent by a voter in the `epoch`-th epoch.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CreditsUsage {
+    pub epoch: u64,
+    pub spent: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoiceCreditsOut {
+    /// current epoch index
+    pub epoch: U64,
+    /// voice credits spent in the current epoch
+    pub spent: U64,
+}
+
+/// Returns the integer square root of `n`.
+pub fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = x / 2 + (x & 1);
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns voice credits spent by the `voter` in the current epoch of the current
+    /// quadratic tally. Returns `None` if the current tally is not quadratic.
+    pub fn voice_credits(&self, voter: ValidAccountId) -> Option<VoiceCreditsOut> {
+        match self.policy(self.policy_version()).tally {
+            Tally::Quadratic { epoch, .. } => {
+                let epoch = current_epoch(epoch);
+                let spent = self.credits_spent(voter.as_ref(), epoch);
+                Some(VoiceCreditsOut {
+                    epoch: epoch.into(),
+                    spent: spent.into(),
+                })
+            }
+            Tally::Linear => None,
+        }
+    }
+}
+
+impl Contract {
+    /// Spends `credits` of the `budget` of the voter in the current epoch and returns
+    /// the number of votes they buy.
+    pub(crate) fn spend_credits(
+        &mut self,
+        voter: &AccountId,
+        budget: u64,
+        epoch: u32,
+        credits: u32,
+    ) -> u32 {
+        assert!(credits > 0, "credits must be positive");
+        let epoch = current_epoch(epoch);
+        let spent = self.credits_spent(voter, epoch);
+        assert!(
+            spent + u64::from(credits) <= budget,
+            "not enough voice credits (available: {}, required: {})",
+            budget - spent.min(budget),
+            credits
+        );
+        self.credits.insert(
+            voter,
+            &CreditsUsage {
+                epoch,
+                spent: spent + u64::from(credits),
+            },
+        );
+        isqrt(credits.into()) as u32
+    }
+
+    fn credits_spent(&self, voter: &AccountId, epoch: u64) -> u64 {
+        self.credits
+            .get(voter)
+            .filter(|c| c.epoch == epoch)
+            .map_or(0, |c| c.spent)
+    }
+}
+
+fn current_epoch(epoch: u32) -> u64 {
+    env::block_timestamp() / FROM_NANO / u64::from(epoch)
+}
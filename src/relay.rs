@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Off-chain signed votes. Voters register an ed25519 vote key and sign vote messages,
+//! which a relayer (or anyone else) submits in batches and pays the gas and storage for.
+
+use std::convert::TryFrom;
+
+use ed25519_dalek::{PublicKey as DalekPublicKey, Signature, Verifier};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::{events, Contract};
+
+/// Maximum number of votes in a `submit_votes` batch. Each vote verifies an ed25519
+/// signature in wasm, so the batch gas grows with the number of votes.
+pub const MAX_VOTES_BATCH: usize = 10;
+
+/// Registered vote key with the last used nonce. The record is kept when the key is
+/// removed, so signed votes can't be replayed after registering the key again.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct VoteKey {
+    /// ed25519 public key (32 bytes).
+    pub public_key: Option<Vec<u8>>,
+    pub nonce: u64,
+}
+
+/// Signed message of an off-chain vote. Voters sign its borsh serialization, see
+/// `vote_message`.
+#[derive(BorshSerialize)]
+pub struct VoteMessage {
+    /// this contract account, so the vote can't be replayed on another DAO.
+    pub contract: AccountId,
+    pub voter: AccountId,
+    pub proposal_id: u32,
+    pub support: bool,
+    /// must be bigger than the last nonce used by the voter.
+    pub nonce: u64,
+}
+
+/// Off-chain vote submitted by a relayer.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedVote {
+    pub voter: ValidAccountId,
+    pub proposal_id: u32,
+    pub support: bool,
+    pub nonce: U64,
+    /// ed25519 signature (64 bytes) of the `VoteMessage`.
+    pub signature: Base64VecU8,
+}
+
+/// JSON compatible return type for `VoteKey`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteKeyOut {
+    pub public_key: Base58PublicKey,
+    /// last nonce used by the voter.
+    pub nonce: U64,
+}
+
+/// Returns true if `signature` is a valid ed25519 signature of `message`.
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let public_key = match DalekPublicKey::from_bytes(public_key) {
+        Ok(k) => k,
+        Err(_) => return false,
+    };
+    match Signature::try_from(signature) {
+        Ok(s) => public_key.verify(message, &s).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Registers the caller ed25519 key to sign off-chain votes, replacing the previous one.
+    `None` removes the key. Votes signed with the key are submitted with `submit_votes`.
+    The caller pays the storage cost. */
+    #[payable]
+    pub fn set_vote_key(&mut self, public_key: Option<Base58PublicKey>) {
+        let a = env::predecessor_account_id();
+        let key = public_key.clone();
+        let public_key = public_key.map(|k| {
+            // the first byte is the key type, 0 for ed25519.
+            assert!(
+                k.0.len() == 33 && k.0[0] == 0,
+                "vote key must be an ed25519 key"
+            );
+            k.0[1..].to_vec()
+        });
+        let storage_start = env::storage_usage();
+        let nonce = self.vote_keys.get(&a).map_or(0, |k| k.nonce);
+        self.vote_keys.insert(&a, &VoteKey { public_key, nonce });
+        events::vote_key_changed(&a, key);
+        self.refund_storage(storage_start, false);
+    }
+
+    /**
+    Applies a batch of off-chain signed votes, like `vote` called by each voter. Each vote
+    must be signed with the voter vote key and use a nonce bigger than the last nonce used
+    by the voter. The batch fails if any vote is not valid.
+    Anyone can submit votes. The caller pays the votes storage and gets it refunded when
+    the proposal is archived. */
+    #[payable]
+    pub fn submit_votes(&mut self, votes: Vec<SignedVote>) {
+        assert!(
+            !votes.is_empty() && votes.len() <= MAX_VOTES_BATCH,
+            "votes batch must have between 1 and {} votes",
+            MAX_VOTES_BATCH
+        );
+        let storage_start = env::storage_usage();
+        for v in votes {
+            let voter: AccountId = v.voter.into();
+            let nonce = u64::from(v.nonce);
+            let mut key = self
+                .vote_keys
+                .get(&voter)
+                .filter(|k| k.public_key.is_some())
+                .unwrap_or_else(|| env::panic(format!("{} has no vote key", voter).as_bytes()));
+            assert!(
+                nonce > key.nonce,
+                "nonce {} of {} was already used",
+                nonce,
+                voter
+            );
+            let message = vote_message(&voter, v.proposal_id, v.support, nonce);
+            assert!(
+                verify_signature(key.public_key.as_ref().unwrap(), &message, &v.signature.0),
+                "vote signature of {} is not valid",
+                voter
+            );
+            key.nonce = nonce;
+            self.vote_keys.insert(&voter, &key);
+            self.record_vote(&voter, v.proposal_id, v.support, None);
+            events::vote_relayed(v.proposal_id, &voter, &env::predecessor_account_id());
+        }
+        self.refund_storage(storage_start, false);
+    }
+
+    /// Returns the registered vote key of the `voter` and the last used nonce.
+    pub fn vote_key(&self, voter: ValidAccountId) -> Option<VoteKeyOut> {
+        let k = self.vote_keys.get(voter.as_ref())?;
+        let mut public_key = vec![0];
+        public_key.extend(k.public_key?);
+        Some(VoteKeyOut {
+            public_key: Base58PublicKey(public_key),
+            nonce: k.nonce.into(),
+        })
+    }
+
+    /// Returns the message to sign for an off-chain vote.
+    pub fn vote_message(
+        &self,
+        voter: ValidAccountId,
+        proposal_id: u32,
+        support: bool,
+        nonce: U64,
+    ) -> Base64VecU8 {
+        vote_message(voter.as_ref(), proposal_id, support, nonce.into()).into()
+    }
+}
+
+fn vote_message(voter: &AccountId, proposal_id: u32, support: bool, nonce: u64) -> Vec<u8> {
+    VoteMessage {
+        contract: env::current_account_id(),
+        voter: voter.clone(),
+        proposal_id,
+        support,
+        nonce,
+    }
+    .try_to_vec()
+    .unwrap()
+}
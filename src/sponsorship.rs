@@ -0,0 +1,98 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::ops::Range;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise, StorageUsage};
+
+use crate::proposal::FROM_NANO;
+use crate::{Contract, STORAGE_PRICE_PER_BYTE};
+
+/// Vote storage paid from the contract balance: up to `cap` per member in each `period`
+/// (in seconds). Zero `cap` disables the sponsorship.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct VoteSponsorship {
+    pub cap: Balance,
+    pub period: u32,
+}
+
+/// Vote storage sponsored for a member in the `period`-th sponsorship period.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct SponsoredUsage {
+    pub period: u64,
+    pub used: Balance,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the amount of vote storage the contract will still pay for the `member` in the
+    /// current sponsorship period.
+    pub fn sponsored_storage_left(&self, member: ValidAccountId) -> U128 {
+        let s = &self.vote_sponsorship;
+        if s.cap == 0 {
+            return 0.into();
+        }
+        let used = self
+            .sponsored_usage(member.as_ref())
+            .map(|u| u.used)
+            .unwrap_or(0);
+        s.cap.saturating_sub(used).into()
+    }
+}
+
+impl Contract {
+    /**
+    Pays the vote storage, used since `storage_start`, from the contract balance when the
+    `member` is a DAO member, its sponsorship cap for the current period allows it and the
+    contract has enough available balance. The `entries` of the proposal voters index, added
+    by the vote, are marked as paid by the treasury, so the storage freed by archiving returns
+    to it. The attached deposit is refunded. The usage record itself is paid by the contract
+    and doesn't count to the cap.
+    Returns false if the storage is not sponsored. */
+    pub(crate) fn sponsor_vote_storage(
+        &mut self,
+        member: &AccountId,
+        proposal_id: u32,
+        entries: Range<u32>,
+        storage_start: StorageUsage,
+    ) -> bool {
+        if self.vote_sponsorship.cap == 0 || self.members.get(member).is_none() {
+            return false;
+        }
+        let mut usage = self.sponsored_usage(member).unwrap_or(SponsoredUsage {
+            period: self.sponsorship_period(),
+            used: 0,
+        });
+        let cost = Balance::from(env::storage_usage() - storage_start) * STORAGE_PRICE_PER_BYTE;
+        if usage.used + cost > self.vote_sponsorship.cap || self.available(None) == 0 {
+            return false;
+        }
+        for n in entries {
+            let key = (proposal_id, n);
+            if let Some(mut e) = self.vote_index.get(&key) {
+                e.payer = None;
+                self.vote_index.insert(&key, &e);
+            }
+        }
+        usage.used += cost;
+        self.sponsored.insert(member, &usage);
+        let deposit = env::attached_deposit();
+        if deposit > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(deposit);
+        }
+        true
+    }
+
+    /// Returns the member sponsorship usage in the current period, if any.
+    fn sponsored_usage(&self, member: &AccountId) -> Option<SponsoredUsage> {
+        let period = self.sponsorship_period();
+        self.sponsored.get(member).filter(|u| u.period == period)
+    }
+
+    fn sponsorship_period(&self) -> u64 {
+        env::block_timestamp() / FROM_NANO / u64::from(self.vote_sponsorship.period)
+    }
+}
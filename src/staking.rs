@@ -0,0 +1,189 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Token weighted voting. Token holders stake NEP-141 governance tokens with
+//! `ft_transfer_call` and, when the token voting is enabled by the `SetTokenVoting` action,
+//! their voting power is the staked amount divided by the policy `token_power_unit`.
+//! Stake of a voter is locked until the end of the voting.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, Gas, Promise, PromiseOrValue,
+    PromiseResult,
+};
+use serde_json::json;
+
+use crate::proposal::{Proposal, FROM_NANO};
+use crate::{events, Contract, STORAGE_PRICE_PER_BYTE};
+
+/// Gas attached to the `ft_transfer` call when unstaking.
+const FT_TRANSFER_GAS: Gas = 10_000_000_000_000;
+/// Gas attached to the `on_unstake` callback.
+const ON_UNSTAKE_GAS: Gas = 10_000_000_000_000;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Stake {
+    pub amount: Balance,
+    /// Unix timestamp (in seconds) until which the stake can't be withdrawn.
+    pub locked_until: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeOut {
+    pub amount: U128,
+    pub locked_until: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    NEP-141 receiver: stakes tokens transferred with `ft_transfer_call` for the sender.
+    Only the staking token (set by `SetTokenVoting`) is accepted. The stake record storage
+    is paid from the sender storage balance (`storage_deposit`), so a new staker must
+    register first. If the call fails, the token contract refunds the tokens. */
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let _ = msg;
+        assert!(
+            self.stake_token.as_ref() == Some(&env::predecessor_account_id()),
+            "only the staking token can be staked"
+        );
+        let sender: AccountId = sender_id.into();
+        let storage_start = env::storage_usage();
+        let mut s = self.stakes.get(&sender).unwrap_or(Stake {
+            amount: 0,
+            locked_until: 0,
+        });
+        s.amount += amount.0;
+        self.stakes.insert(&sender, &s);
+        self.total_staked += amount.0;
+        if env::storage_usage() > storage_start {
+            assert!(
+                self.storage_deposits.contains_key(&sender),
+                "{} must register with storage_deposit before staking",
+                sender
+            );
+            let cost = Balance::from(env::storage_usage() - storage_start) * STORAGE_PRICE_PER_BYTE;
+            self.storage_charge(&sender, cost);
+        }
+        events::stake_changed(&sender, s.amount);
+        PromiseOrValue::Value(0.into())
+    }
+
+    /// Withdraws staked tokens. Stake is locked until the end of the voting of all proposals
+    /// the caller voted on. Requires exactly 1 yocto NEAR attached.
+    #[payable]
+    pub fn unstake(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        let a = env::predecessor_account_id();
+        let mut s = self.stakes.get(&a).expect("no stake found");
+        let amount: Balance = amount.into();
+        assert!(
+            amount > 0 && amount <= s.amount,
+            "amount must be positive and not bigger than the stake"
+        );
+        assert!(
+            env::block_timestamp() / FROM_NANO > s.locked_until,
+            "stake is locked until {}",
+            s.locked_until
+        );
+        s.amount -= amount;
+        self.stakes.insert(&a, &s);
+        self.total_staked -= amount;
+        events::stake_changed(&a, s.amount);
+        let token = self.stake_token.clone().unwrap();
+        Promise::new(token)
+            .function_call(
+                b"ft_transfer".to_vec(),
+                json!({ "receiver_id": a, "amount": U128(amount) })
+                    .to_string()
+                    .into_bytes(),
+                1,
+                FT_TRANSFER_GAS,
+            )
+            .then(
+                Promise::new(env::current_account_id()).function_call(
+                    b"on_unstake".to_vec(),
+                    json!({ "account": a, "amount": U128(amount) })
+                        .to_string()
+                        .into_bytes(),
+                    0,
+                    ON_UNSTAKE_GAS,
+                ),
+            )
+    }
+
+    /// Callback of the unstake transfer. Restores the stake if the transfer failed.
+    #[private]
+    pub fn on_unstake(&mut self, account: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            let mut s = self.stakes.get(&account).unwrap();
+            s.amount += amount.0;
+            self.stakes.insert(&account, &s);
+            self.total_staked += amount.0;
+            events::stake_changed(&account, s.amount);
+        }
+    }
+
+    pub fn stake(&self, account: ValidAccountId) -> Option<StakeOut> {
+        self.stakes.get(account.as_ref()).map(|s| StakeOut {
+            amount: s.amount.into(),
+            locked_until: s.locked_until.into(),
+        })
+    }
+}
+
+impl Contract {
+    /**
+    Returns the account voting power for the proposal: the staked tokens power when the
+    proposal policy uses the token voting, the member power otherwise. In the token voting
+    the stake is locked until the proposal voting end. */
+    pub(crate) fn take_voting_power(&mut self, account: &AccountId, p: &Proposal) -> u32 {
+        let unit = match self.policy(p.policy_version).token_power_unit {
+            Some(unit) => unit.0,
+            None => return self.power_at(account, p.policy_version).into(),
+        };
+        let mut s = match self.stakes.get(account) {
+            Some(s) => s,
+            None => return 0,
+        };
+        let power = (s.amount / unit).min(u32::MAX.into()) as u32;
+        if power > 0 && s.locked_until < p.voting_end {
+            s.locked_until = p.voting_end;
+            self.stakes.insert(account, &s);
+        }
+        power
+    }
+
+    /// Sets the staking token and the token voting power unit. Zero `power_unit` switches
+    /// back to the members voting. Creates a new policy version.
+    pub(crate) fn set_token_voting(&mut self, token: &AccountId, power_unit: Balance) {
+        if let Some(t) = &self.stake_token {
+            assert!(
+                t == token || self.total_staked == 0,
+                "staking token can't be changed while tokens are staked"
+            );
+        }
+        self.stake_token = Some(token.clone());
+        self.new_policy_version(|p| {
+            p.token_power_unit = if power_unit > 0 {
+                Some(power_unit.into())
+            } else {
+                None
+            }
+        });
+        events::settings_changed(
+            "token_voting",
+            json!({ "token": token, "power_unit": U128(power_unit) }),
+        );
+    }
+}
@@ -0,0 +1,191 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! NEP-145 storage management. Accounts prepay storage once and `vote`, `add_proposal` and
+//! other methods draw the storage cost from that balance, when the attached deposit is
+//! not enough.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, Promise, StorageUsage};
+
+use crate::{events, Contract, STORAGE_PRICE_PER_BYTE};
+
+/// Storage used by an account registration: a storage balance record with the longest
+/// account id (1 byte prefix + 68 bytes key + 16 bytes value + 40 bytes of record overhead).
+const ACCOUNT_STORAGE: StorageUsage = 125;
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+fn storage_balance(total: Balance) -> StorageBalance {
+    StorageBalance {
+        total: total.into(),
+        available: (total - min_storage_balance()).into(),
+    }
+}
+
+fn min_storage_balance() -> Balance {
+    Balance::from(ACCOUNT_STORAGE) * STORAGE_PRICE_PER_BYTE
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Deposits the attached amount to the storage balance of `account_id` (the caller by
+    default). Unregistered account must deposit at least `storage_balance_bounds().min`.
+    With `registration_only`, only the minimum balance is taken and the rest is refunded. */
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let mut amount = env::attached_deposit();
+        let account: AccountId = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let balance = match self.storage_deposits.get(&account) {
+            Some(b) => {
+                if registration_only {
+                    if amount > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(amount);
+                    }
+                    return storage_balance(b);
+                }
+                b
+            }
+            None => {
+                let min = min_storage_balance();
+                assert!(
+                    amount >= min,
+                    "The attached deposit is less than the minimum storage balance ({})",
+                    min
+                );
+                if registration_only {
+                    if amount > min {
+                        Promise::new(env::predecessor_account_id()).transfer(amount - min);
+                    }
+                    amount = min;
+                }
+                0
+            }
+        };
+        let balance = balance + amount;
+        self.storage_deposits.insert(&account, &balance);
+        self.storage_deposits_total += amount;
+        events::storage("storage_deposit", &account, amount, balance);
+        storage_balance(balance)
+    }
+
+    /// Withdraws `amount` (all available balance by default) from the caller storage
+    /// balance. Requires exactly 1 yocto NEAR attached.
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account = env::predecessor_account_id();
+        let balance = self
+            .storage_deposits
+            .get(&account)
+            .expect(&format!("The account {} is not registered", account));
+        let available = balance - min_storage_balance();
+        let amount = amount.map(Balance::from).unwrap_or(available);
+        assert!(
+            amount <= available,
+            "The amount is greater than the available storage balance"
+        );
+        let balance = balance - amount;
+        self.storage_deposits.insert(&account, &balance);
+        self.storage_deposits_total -= amount;
+        events::storage("storage_withdraw", &account, amount, balance);
+        if amount > 0 {
+            Promise::new(account).transfer(amount);
+        }
+        storage_balance(balance)
+    }
+
+    /**
+    Unregisters the caller and transfers the whole storage balance back. Storage already
+    paid from the balance (eg votes) is not released, its later refunds are transferred to
+    the caller. Panics if the caller has staked tokens, unless `force` is set: the caller is
+    unregistered anyway and the tokens stay withdrawable with `unstake`.
+    Returns false if the caller is not registered. Requires exactly 1 yocto NEAR attached. */
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account = env::predecessor_account_id();
+        if !force.unwrap_or(false) {
+            assert!(
+                self.stakes.get(&account).map_or(true, |s| s.amount == 0),
+                "The account has staked tokens, unstake them or use force"
+            );
+        }
+        match self.storage_deposits.remove(&account) {
+            Some(balance) => {
+                self.storage_deposits_total -= balance;
+                events::storage("storage_unregister", &account, balance, 0);
+                Promise::new(account).transfer(balance);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: min_storage_balance().into(),
+            max: None,
+        }
+    }
+
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.storage_deposits
+            .get(account_id.as_ref())
+            .map(storage_balance)
+    }
+}
+
+impl Contract {
+    /// Draws `amount` from the account storage balance.
+    pub(crate) fn storage_charge(&mut self, account: &AccountId, amount: Balance) {
+        let balance = self.storage_deposits.get(account).unwrap();
+        let available = balance - min_storage_balance();
+        assert!(
+            amount <= available,
+            "not enough storage balance (available: {}, required: {})",
+            available,
+            amount
+        );
+        self.storage_deposits.insert(account, &(balance - amount));
+        self.storage_deposits_total -= amount;
+    }
+
+    /// Adds `amount` to the account storage balance if the account is registered,
+    /// otherwise transfers it to the account.
+    pub(crate) fn storage_refund(&mut self, account: &AccountId, amount: Balance) {
+        match self.storage_deposits.get(account) {
+            Some(balance) => {
+                self.storage_deposits.insert(account, &(balance + amount));
+                self.storage_deposits_total += amount;
+            }
+            None => {
+                Promise::new(account.clone()).transfer(amount);
+            }
+        }
+    }
+}
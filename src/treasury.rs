@@ -0,0 +1,27 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::Balance;
+use uint::construct_uint;
+
+construct_uint! {
+    /// 256-bit unsigned integer used for intermediate results of balance arithmetic.
+    pub struct U256(4);
+}
+
+/// Commitment reserves funds for a passing proposal which is not executed yet.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Commitment {
+    pub amount: Balance,
+    /// Last timestamp (in seconds) when the proposal can be executed. After that time the
+    /// commitment is released by `archive_proposal`.
+    pub execute_before: u64,
+}
+
+/// Returns `a * b / c`. The intermediate product is computed on 256 bits, so it doesn't
+/// overflow. The result must fit in 128 bits.
+pub fn mul_div(a: Balance, b: u128, c: u128) -> Balance {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
@@ -0,0 +1,104 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{assert_one_yocto, env, near_bindgen, Gas, Promise};
+
+use crate::{events, Contract};
+
+/// Gas attached to the `migrate` call after deploying a new contract code. `migrate` doesn't
+/// rewrite proposals, so its cost doesn't grow with the DAO history.
+const MIGRATE_GAS: Gas = 50_000_000_000_000;
+
+/// Returns storage key of the contract code with the given sha256 hash.
+pub fn code_key(code_hash: &[u8]) -> Vec<u8> {
+    let mut key = b"c".to_vec();
+    key.extend_from_slice(code_hash);
+    key
+}
+
+/// Returns stored contract code with the given sha256 hash.
+pub fn read_code(code_hash: &[u8]) -> Vec<u8> {
+    env::storage_read(&code_key(code_hash)).expect("code not found")
+}
+
+#[near_bindgen]
+impl Contract {
+    /**
+    Stores a contract code, passed as the raw function call input, for a future `Upgrade`
+    proposal. Can be called by anyone. The caller has to attach a deposit to cover the
+    storage cost, the change is refunded. The storage is refunded to the uploader when the
+    code is successfully deployed by `Upgrade` or removed with `remove_code`.
+    Returns the sha256 hash of the code. */
+    #[payable]
+    pub fn store_code(&mut self) -> Base64VecU8 {
+        let code = env::input().expect("code not provided");
+        assert!(!code.is_empty(), "code not provided");
+        let code_hash = env::sha256(&code);
+        let storage_start = env::storage_usage();
+        if self.code_uploaders.get(&code_hash).is_none() {
+            env::storage_write(&code_key(&code_hash), &code);
+            self.code_uploaders
+                .insert(&code_hash, &env::predecessor_account_id());
+        }
+        self.refund_storage(storage_start, false);
+        events::code_stored(&code_hash, code.len());
+        code_hash.into()
+    }
+
+    /// Removes a code stored by the caller and refunds its storage. A proposal using
+    /// the code can't be executed afterwards. Requires exactly 1 yocto NEAR attached.
+    #[payable]
+    pub fn remove_code(&mut self, code_hash: Base64VecU8) {
+        assert_one_yocto();
+        let code_hash: Vec<u8> = code_hash.into();
+        let uploader = self.code_uploaders.get(&code_hash).expect("code not found");
+        assert!(
+            env::predecessor_account_id() == uploader,
+            "only the uploader ({}) can remove the code",
+            uploader
+        );
+        self.release_code(&code_hash);
+        events::code_removed(&code_hash);
+    }
+
+    /// Returns true if a code with the given sha256 hash is stored.
+    pub fn has_code(&self, code_hash: Base64VecU8) -> bool {
+        env::storage_has_key(&code_key(&Vec::from(code_hash)))
+    }
+}
+
+impl Contract {
+    /// Deploys the stored code and migrates the contract state. The stored code is kept until
+    /// the upgrade succeeds (see `on_upgraded`), so a failed upgrade can be proposed again.
+    pub(crate) fn upgrade(&mut self, code_hash: &[u8]) -> Promise {
+        let code = read_code(code_hash);
+        events::settings_changed(
+            "code_hash",
+            serde_json::json!(Base64VecU8::from(code_hash.to_vec())),
+        );
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(b"migrate".to_vec(), Vec::new(), 0, MIGRATE_GAS)
+    }
+
+    /// Removes the deployed code, unless its uploader already removed it, and refunds its
+    /// storage to the uploader. Called by `on_execute` after a successful upgrade.
+    pub(crate) fn on_upgraded(&mut self, code_hash: &[u8]) {
+        if self.code_uploaders.get(&code_hash.to_vec()).is_some() {
+            self.release_code(code_hash);
+        }
+    }
+
+    /// Removes the stored code and refunds the freed storage to the uploader.
+    fn release_code(&mut self, code_hash: &[u8]) {
+        let storage_start = env::storage_usage();
+        let uploader = self
+            .code_uploaders
+            .remove(&code_hash.to_vec())
+            .expect("code not found");
+        env::storage_remove(&code_key(code_hash));
+        self.refund_freed(&uploader, storage_start);
+    }
+}
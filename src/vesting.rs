@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::proposal::FROM_NANO;
+use crate::treasury::mul_div;
+use crate::{events, Contract};
+
+/// Vesting grant created by an executed proposal. Nothing is vested before the cliff, then
+/// the grant vests linearly from `start` until `start + duration`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Grant {
+    pub beneficiary: AccountId,
+    pub total: Balance,
+    /// Unix timestamp (in seconds) when vesting starts.
+    pub start: u64,
+    /// Time (in seconds) after `start` before which nothing can be withdrawn.
+    pub cliff: u32,
+    /// Time (in seconds) after `start` when the whole grant is vested.
+    pub duration: u32,
+    pub revocable: bool,
+    /// Timestamp (in seconds) when the grant was revoked. Vesting stops at that time.
+    pub revoked_at: Option<u64>,
+    /// Amount already withdrawn by the beneficiary.
+    pub withdrawn: Balance,
+}
+
+impl Grant {
+    /// Returns the amount vested at time `t` (in seconds), including the withdrawn amount.
+    pub fn vested(&self, t: u64) -> Balance {
+        let t = match self.revoked_at {
+            Some(r) if r < t => r,
+            _ => t,
+        };
+        if t < self.start + u64::from(self.cliff) {
+            return 0;
+        }
+        let elapsed = t - self.start;
+        if elapsed >= u64::from(self.duration) {
+            return self.total;
+        }
+        mul_div(self.total, elapsed.into(), self.duration.into())
+    }
+
+    /// Returns the amount which is still owed to the beneficiary.
+    pub fn remaining(&self) -> Balance {
+        match self.revoked_at {
+            Some(r) => self.vested(r) - self.withdrawn,
+            None => self.total - self.withdrawn,
+        }
+    }
+}
+
+/// Asserts that grant parameters are valid.
+pub fn assert_valid_grant(cliff: u32, duration: u32) {
+    assert!(duration > 0, "grant duration must be positive");
+    assert!(
+        cliff <= duration,
+        "grant cliff must not be longer than the grant duration"
+    );
+}
+
+/// JSON compatible return type for Grant.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "test", derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct GrantOut {
+    pub beneficiary: AccountId,
+    pub total: U128,
+    pub start: U64,
+    pub cliff: u32,
+    pub duration: u32,
+    pub revocable: bool,
+    pub revoked_at: Option<U64>,
+    pub withdrawn: U128,
+    /// amount vested at the current block timestamp
+    pub vested: U128,
+}
+
+impl GrantOut {
+    /// Creates GrantOut with amount vested at time `t` (in seconds).
+    pub fn new(g: Grant, t: u64) -> Self {
+        GrantOut {
+            vested: g.vested(t).into(),
+            beneficiary: g.beneficiary,
+            total: g.total.into(),
+            start: g.start.into(),
+            cliff: g.cliff,
+            duration: g.duration,
+            revocable: g.revocable,
+            revoked_at: g.revoked_at.map(U64::from),
+            withdrawn: g.withdrawn.into(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Transfers the vested and not yet withdrawn part of a grant to its beneficiary.
+    /// Can be called only by the grant beneficiary.
+    pub fn withdraw_vested(&mut self, grant_id: u32) -> Promise {
+        let idx: u64 = grant_id.into();
+        let mut g = self.grants.get(idx).expect("grant_id not found");
+        assert!(
+            env::predecessor_account_id() == g.beneficiary,
+            "only the beneficiary can withdraw from the grant"
+        );
+        let vested = g.vested(env::block_timestamp() / FROM_NANO);
+        assert!(vested > g.withdrawn, "nothing to withdraw");
+        let amount = vested - g.withdrawn;
+        g.withdrawn = vested;
+        self.grants.replace(idx, &g);
+        self.reserved -= amount;
+        events::treasury("grant_withdrawn", Some(grant_id), &g.beneficiary, amount);
+        Promise::new(g.beneficiary).transfer(amount)
+    }
+
+    /// Returns vesting grant by id.
+    /// Panics when `grant_id` is not found.
+    pub fn grant(&self, grant_id: u32) -> GrantOut {
+        let g = self
+            .grants
+            .get(grant_id.into())
+            .expect("grant_id not found");
+        GrantOut::new(g, env::block_timestamp() / FROM_NANO)
+    }
+}
+
+impl Contract {
+    /// Creates a new grant and reserves funds for it.
+    pub(crate) fn create_grant(&mut self, g: Grant) {
+        self.reserved += g.remaining();
+        self.grants.push(&g);
+        let id = (self.grants.len() - 1) as u32;
+        events::treasury("grant_created", Some(id), &g.beneficiary, g.remaining());
+    }
+
+    /// Stops vesting of a revocable grant and releases its unvested funds.
+    pub(crate) fn revoke_grant(&mut self, grant_id: u32) {
+        let idx: u64 = grant_id.into();
+        let mut g = self.grants.get(idx).expect("grant_id not found");
+        assert!(g.revocable, "grant is not revocable");
+        assert!(g.revoked_at.is_none(), "grant already revoked");
+        let remaining = g.remaining();
+        g.revoked_at = Some(env::block_timestamp() / FROM_NANO);
+        self.reserved -= remaining - g.remaining();
+        self.grants.replace(idx, &g);
+        events::treasury("grant_revoked", Some(grant_id), &g.beneficiary, 0);
+    }
+}